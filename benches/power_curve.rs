@@ -0,0 +1,30 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+#[allow(dead_code)]
+#[path = "../src/power_curve.rs"]
+mod power_curve;
+
+use power_curve::calculate_mean_max_curve;
+
+fn synthetic_ride(seconds: usize) -> Vec<f64> {
+    (0..seconds as u64)
+        .map(|i| (150 + (i * 7919) % 250) as f64)
+        .collect()
+}
+
+fn bench_power_curve(c: &mut Criterion) {
+    let mut group = c.benchmark_group("calculate_mean_max_curve");
+    group.sample_size(10);
+    for hours in [1, 3, 6] {
+        let ride = synthetic_ride(hours * 3600);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{hours}h")),
+            &ride,
+            |b, ride| b.iter(|| calculate_mean_max_curve(black_box(ride))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_power_curve);
+criterion_main!(benches);
@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use fitparser::{profile::MesgNum, FitDataRecord};
+use serde::Deserialize;
+
+use crate::channels::{channel_series, ChannelCurves};
+use crate::compliance::score_compliance;
+use crate::critical_power::{fit_critical_power, CriticalPowerModel};
+use crate::error::{ensure_positive, AppError};
+use crate::intervals::{detect_intervals, IntervalSettings};
+use crate::laps::LapStats;
+use crate::metrics::{
+    ftp_from_fit_data, hr_limits_from_fit_data, ActivityMetrics, DEFAULT_MAX_HR, DEFAULT_RESTING_HR,
+};
+use crate::power_curve::per_kilogram;
+use crate::resample::{record_samples, GapHandling, DEFAULT_MAX_GAP};
+use crate::structures::*;
+use crate::summary::ActivitySummary;
+use crate::users::{AthleteSettings, UserProfile};
+use crate::wbal::calculate_w_prime_balance;
+use crate::zones::{
+    deserialize_zones, scale_zones, time_in_zones, TimeInZones, COGGAN_POWER_ZONES, LTHR_ZONES,
+    MAX_HR_ZONES,
+};
+
+/// Explicit overrides for a single upload. Anything left unset falls back to
+/// the user's profile settings on the activity date, then to what the device
+/// recorded in the file, then to defaults.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AnalysisOptions {
+    pub gap_handling: Option<GapHandling>,
+    pub max_gap: Option<i64>,
+    pub ftp: Option<f64>,
+    /// Critical power in watts and W′ in joules for W′bal, only set together.
+    pub cp: Option<f64>,
+    pub w_prime: Option<f64>,
+    pub lthr: Option<f64>,
+    pub max_hr: Option<f64>,
+    pub resting_hr: Option<f64>,
+    /// Body weight in kilograms for the W/kg variants.
+    pub weight: Option<f64>,
+    /// Also store the untyped FIT messages next to the typed activity.
+    #[serde(default)]
+    pub keep_raw: bool,
+    /// Fraction of FTP an effort has to exceed to be detected as an interval.
+    pub interval_threshold: Option<f64>,
+    pub interval_min_duration: Option<usize>,
+    /// Efforts at most this many seconds apart are merged into one interval.
+    pub interval_merge_gap: Option<usize>,
+    /// Comma separated upper bounds in watts, overriding the FTP based zones.
+    #[serde(default, deserialize_with = "deserialize_zones")]
+    pub power_zones: Option<Vec<f64>>,
+    /// Comma separated upper bounds in bpm, overriding the LTHR/max HR zones.
+    #[serde(default, deserialize_with = "deserialize_zones")]
+    pub hr_zones: Option<Vec<f64>>,
+}
+
+impl AnalysisOptions {
+    pub fn validate(&self) -> Result<(), AppError> {
+        ensure_positive("ftp", self.ftp)?;
+        ensure_positive("cp", self.cp)?;
+        ensure_positive("w_prime", self.w_prime)?;
+        ensure_positive("lthr", self.lthr)?;
+        ensure_positive("max_hr", self.max_hr)?;
+        ensure_positive("resting_hr", self.resting_hr)?;
+        ensure_positive("weight", self.weight)?;
+        // a gap of 0 s would break the series at every sample
+        ensure_positive("max_gap", self.max_gap.map(|gap| gap as f64))?;
+        if self.cp.is_some() != self.w_prime.is_some() {
+            return Err(AppError::Validation(
+                "cp and w_prime must be given together".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Parses a FIT file's records and derives everything we store per activity.
+/// `content_hash` is left to the caller, which has the file's bytes.
+pub fn analyse_activity(
+    user_id: &str,
+    records: Vec<FitDataRecord>,
+    options: &AnalysisOptions,
+    profile: Option<&UserProfile>,
+) -> MongoSchema {
+    let activity = Activity::from_records(records.iter().cloned());
+    let device_weight = activity
+        .user_profile
+        .as_ref()
+        .map(|profile| profile.weight.value)
+        .filter(|weight| *weight > 0.0);
+    let data: FitDataMap = records.into_iter().fold(BTreeMap::new(), merge_by_kind);
+    let summary = ActivitySummary::from_fit_data(&data);
+    let settings = profile
+        .and_then(|profile| profile.settings_at(summary.start_time.unwrap_or_else(Utc::now)))
+        .cloned();
+    let setting = |get: fn(&AthleteSettings) -> Option<f64>| settings.as_ref().and_then(get);
+
+    let gap_handling = options.gap_handling.unwrap_or_default();
+    let max_gap = options.max_gap.unwrap_or(DEFAULT_MAX_GAP);
+    let curves = ChannelCurves::calculate(&data, summary.sport.as_deref(), gap_handling, max_gap);
+    let power_series = channel_series(&data, "power", gap_handling, max_gap);
+    let hr_series = channel_series(&data, "heart_rate", gap_handling, max_gap);
+
+    let (device_resting_hr, device_max_hr) = hr_limits_from_fit_data(&data);
+    let ftp = options
+        .ftp
+        .or(setting(|s| s.ftp))
+        .or_else(|| ftp_from_fit_data(&data));
+    let resting_hr = options
+        .resting_hr
+        .or(setting(|s| s.resting_hr))
+        .or(device_resting_hr)
+        .unwrap_or(DEFAULT_RESTING_HR);
+    let max_hr = options
+        .max_hr
+        .or(setting(|s| s.max_hr))
+        .or(device_max_hr)
+        .unwrap_or(DEFAULT_MAX_HR);
+    let lthr = options.lthr.or(setting(|s| s.threshold_hr));
+    let weight = options
+        .weight
+        .or(setting(|s| s.weight))
+        .or(device_weight)
+        .filter(|weight| *weight > 0.0);
+
+    let metrics = ActivityMetrics::calculate(&power_series, ftp)
+        .with_heart_rate(&hr_series, resting_hr, max_hr)
+        .with_weight(weight);
+
+    let power_zones = options
+        .power_zones
+        .clone()
+        .or_else(|| settings.as_ref().and_then(|s| s.power_zones.clone()))
+        .or_else(|| ftp.map(|ftp| scale_zones(&COGGAN_POWER_ZONES, ftp)))
+        .unwrap_or_default();
+    let hr_zones = options
+        .hr_zones
+        .clone()
+        .or_else(|| settings.as_ref().and_then(|s| s.hr_zones.clone()))
+        .unwrap_or_else(|| match lthr {
+            Some(lthr) => scale_zones(&LTHR_ZONES, lthr),
+            None => scale_zones(&MAX_HR_ZONES, max_hr),
+        });
+    let time_in_zones = TimeInZones {
+        power: time_in_zones(&power_series, &power_zones),
+        heart_rate: time_in_zones(&hr_series, &hr_zones),
+        device: activity
+            .time_in_zones
+            .iter()
+            .find(|zones| zones.reference_mesg == "session")
+            .or(activity.time_in_zones.last())
+            .cloned(),
+    };
+
+    let power_samples = record_samples(&data, "power");
+    let laps = activity
+        .laps
+        .iter()
+        .map(|lap| LapStats::calculate(lap, &power_samples, ftp, gap_handling, max_gap))
+        .collect::<Vec<_>>();
+    let workout_name = activity
+        .workout
+        .as_ref()
+        .map(|workout| workout.wkt_name.clone())
+        .filter(|name| !name.is_empty());
+    let compliance = score_compliance(
+        workout_name,
+        &activity.workout_steps,
+        &laps,
+        &power_samples,
+        ftp,
+    );
+
+    let defaults = IntervalSettings::default();
+    let interval_settings = IntervalSettings {
+        threshold: options.interval_threshold.unwrap_or(defaults.threshold),
+        min_duration: options
+            .interval_min_duration
+            .unwrap_or(defaults.min_duration),
+        merge_gap: options.interval_merge_gap.unwrap_or(defaults.merge_gap),
+    };
+    let intervals = ftp
+        .map(|ftp| {
+            let heart_rate = record_samples(&data, "heart_rate");
+            detect_intervals(&power_samples, &heart_rate, ftp, interval_settings)
+        })
+        .unwrap_or_default();
+
+    // CP and W′ come as a pair, from the options or the activity's own curve
+    let model = options.cp.zip(options.w_prime).or_else(|| {
+        fit_critical_power(&curves.power_curve, CriticalPowerModel::TwoParameter)
+            .map(|fit| (fit.cp, fit.w_prime))
+    });
+    let w_prime_balance = model.and_then(|(cp, w_prime)| {
+        calculate_w_prime_balance(&record_samples(&data, "power"), cp, w_prime)
+    });
+
+    MongoSchema {
+        user_id: user_id.to_owned(),
+        summary,
+        schema_version: SCHEMA_VERSION,
+        content_hash: None,
+        file_fingerprint: activity.file_id.as_ref().and_then(FileId::fingerprint),
+        activity,
+        fit_data: options.keep_raw.then(|| {
+            let mut raw = data;
+            raw.remove(&MesgNum::Record);
+            raw
+        }),
+        power_curve_wkg: weight.map(|weight| per_kilogram(&curves.power_curve, weight)),
+        power_curve: curves.power_curve,
+        hr_curve: curves.hr_curve,
+        speed_curve: curves.speed_curve,
+        cadence_curve: curves.cadence_curve,
+        pace_curve: curves.pace_curve,
+        metrics,
+        w_prime_balance,
+        time_in_zones,
+        laps,
+        intervals,
+        compliance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rejects_non_positive_max_gap() {
+        for max_gap in [0, -30] {
+            let options = AnalysisOptions {
+                gap_handling: Some(GapHandling::Break),
+                max_gap: Some(max_gap),
+                ..Default::default()
+            };
+            assert!(matches!(options.validate(), Err(AppError::Validation(_))));
+        }
+        let options = AnalysisOptions {
+            max_gap: Some(10),
+            ..Default::default()
+        };
+        assert!(options.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_requires_cp_with_w_prime() {
+        let options = AnalysisOptions {
+            cp: Some(250.0),
+            ..Default::default()
+        };
+        assert!(matches!(options.validate(), Err(AppError::Validation(_))));
+    }
+}
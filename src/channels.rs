@@ -0,0 +1,127 @@
+use crate::power_curve::{calculate_segmented_mean_max_curve, MeanMaxCurve};
+use crate::resample::{record_samples, resample, GapHandling};
+use crate::structures::FitDataMap;
+
+/// Mean-maximal curves for every `Record` channel we analyse.
+#[derive(Clone, Debug, Default)]
+pub struct ChannelCurves {
+    pub power_curve: MeanMaxCurve,
+    pub hr_curve: MeanMaxCurve,
+    pub speed_curve: MeanMaxCurve,
+    pub cadence_curve: MeanMaxCurve,
+    /// Best pace in seconds per kilometer, only filled in for runs.
+    pub pace_curve: Option<MeanMaxCurve>,
+}
+
+/// 1 Hz segments of a `Record` channel, empty when the channel never reports
+/// a non-zero value (e.g. no power meter paired).
+pub fn channel_series(
+    data: &FitDataMap,
+    field: &str,
+    gap_handling: GapHandling,
+    max_gap: i64,
+) -> Vec<Vec<f64>> {
+    let samples = record_samples(data, field);
+    if samples.iter().all(|&(_, value)| value == 0.0) {
+        return vec![];
+    }
+    resample(&samples, gap_handling, max_gap)
+}
+
+fn channel_curve(
+    data: &FitDataMap,
+    field: &str,
+    gap_handling: GapHandling,
+    max_gap: i64,
+) -> MeanMaxCurve {
+    calculate_segmented_mean_max_curve(&channel_series(data, field, gap_handling, max_gap))
+}
+
+fn pace_curve(speed_curve: &MeanMaxCurve) -> MeanMaxCurve {
+    speed_curve
+        .iter()
+        .filter(|&&(_, speed)| speed > 0.0)
+        .map(|&(duration, speed)| (duration, 1000.0 / speed))
+        .collect()
+}
+
+impl ChannelCurves {
+    pub fn calculate(
+        data: &FitDataMap,
+        sport: Option<&str>,
+        gap_handling: GapHandling,
+        max_gap: i64,
+    ) -> Self {
+        let mut speed_curve = channel_curve(data, "enhanced_speed", gap_handling, max_gap);
+        if speed_curve.is_empty() {
+            speed_curve = channel_curve(data, "speed", gap_handling, max_gap);
+        }
+        ChannelCurves {
+            power_curve: channel_curve(data, "power", gap_handling, max_gap),
+            hr_curve: channel_curve(data, "heart_rate", gap_handling, max_gap),
+            cadence_curve: channel_curve(data, "cadence", gap_handling, max_gap),
+            pace_curve: (sport == Some("running")).then(|| pace_curve(&speed_curve)),
+            speed_curve,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fitparser::{profile::MesgNum, Value};
+
+    use super::*;
+    use crate::resample::DEFAULT_MAX_GAP;
+    use crate::test_support::{fit_data, fit_record, fit_time};
+
+    /// 10 s of records carrying `fields` every second.
+    fn records(fields: &[(&str, f64)]) -> FitDataMap {
+        fit_data(
+            (0..10)
+                .map(|i| {
+                    let mut values = vec![("timestamp", fit_time(1_700_000_000 + i))];
+                    values.extend(
+                        fields
+                            .iter()
+                            .map(|&(name, value)| (name, Value::Float64(value))),
+                    );
+                    fit_record(MesgNum::Record, values)
+                })
+                .collect(),
+        )
+    }
+
+    fn curves(data: &FitDataMap, sport: &str) -> ChannelCurves {
+        ChannelCurves::calculate(data, Some(sport), GapHandling::ZeroFill, DEFAULT_MAX_GAP)
+    }
+
+    #[test]
+    fn channels_without_values_have_no_series() {
+        let data = records(&[("power", 0.0), ("heart_rate", 150.0)]);
+        let series = |field| channel_series(&data, field, GapHandling::ZeroFill, DEFAULT_MAX_GAP);
+        assert!(series("power").is_empty());
+        assert!(series("cadence").is_empty());
+        assert_eq!(series("heart_rate"), vec![vec![150.0; 10]]);
+        assert!(curves(&data, "cycling").power_curve.is_empty());
+    }
+
+    #[test]
+    fn speed_falls_back_to_the_speed_field() {
+        let legacy = curves(&records(&[("speed", 4.0)]), "cycling");
+        assert_eq!(legacy.speed_curve.first(), Some(&(1, 4.0)));
+
+        let enhanced = curves(
+            &records(&[("speed", 4.0), ("enhanced_speed", 5.0)]),
+            "cycling",
+        );
+        assert_eq!(enhanced.speed_curve.first(), Some(&(1, 5.0)));
+    }
+
+    #[test]
+    fn pace_curve_is_only_built_for_runs() {
+        let data = records(&[("enhanced_speed", 4.0)]);
+        assert_eq!(curves(&data, "cycling").pace_curve, None);
+        let pace = curves(&data, "running").pace_curve.unwrap();
+        assert_eq!(pace.first(), Some(&(1, 250.0)));
+    }
+}
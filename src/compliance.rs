@@ -0,0 +1,371 @@
+use serde::{Deserialize, Serialize};
+
+use crate::laps::LapStats;
+use crate::structures::WorkoutStep;
+
+/// Most passes through a repeated block that are unrolled, as the count
+/// comes straight from the file.
+const MAX_REPETITIONS: usize = 100;
+/// Most planned steps a workout unrolls to.
+const MAX_PLANNED_STEPS: usize = 10_000;
+
+/// How a single pass through a planned step was executed. Compliance values
+/// are fractions of the plan, capped at 1.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StepCompliance {
+    pub step_index: i64,
+    /// 1-based pass through the step when it is part of a repeated block.
+    pub repetition: usize,
+    pub name: Option<String>,
+    pub intensity: Option<String>,
+    /// Index of the lap the step was executed in, `None` when skipped.
+    pub lap_index: Option<i64>,
+    pub planned_duration: Option<f64>, // seconds
+    pub actual_duration: f64,          // seconds
+    pub duration_compliance: Option<f64>,
+    pub target_power_low: Option<f64>,
+    pub target_power_high: Option<f64>,
+    pub actual_power: Option<f64>,
+    pub power_compliance: Option<f64>,
+    /// Mean of the duration and power compliance that apply to the step.
+    pub score: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ComplianceReport {
+    pub workout_name: Option<String>,
+    pub planned_duration: f64, // seconds of the timed steps
+    pub actual_duration: f64,
+    pub steps: Vec<StepCompliance>,
+    /// Step scores weighted by planned duration; skipped steps count as 0.
+    pub score: Option<f64>,
+}
+
+fn is_repeat(step: &WorkoutStep) -> bool {
+    step.duration_type.starts_with("repeat")
+}
+
+/// The planned steps in execution order, with repeated blocks unrolled, as
+/// `(step, repetition)`. Blocks repeat at most `MAX_REPETITIONS` times and
+/// the plan is cut off after `MAX_PLANNED_STEPS`.
+fn unroll(steps: &[WorkoutStep]) -> Vec<(&WorkoutStep, usize)> {
+    let mut planned: Vec<(&WorkoutStep, usize)> = Vec::new();
+    for step in steps {
+        if !is_repeat(step) {
+            planned.push((step, 1));
+            continue;
+        }
+        let (Some(from), Some(times)) = (step.duration_step, step.repeat_steps) else {
+            continue;
+        };
+        let block: Vec<&WorkoutStep> = steps
+            .iter()
+            .filter(|s| {
+                !is_repeat(s) && from <= s.message_index && s.message_index < step.message_index
+            })
+            .collect();
+        let times = times.clamp(1, MAX_REPETITIONS as i64) as usize;
+        for repetition in 2..=times {
+            planned.extend(block.iter().map(|s| (*s, repetition)));
+            if planned.len() >= MAX_PLANNED_STEPS {
+                break;
+            }
+        }
+    }
+    planned.truncate(MAX_PLANNED_STEPS);
+    planned
+}
+
+/// Power target of `step` in watts, resolving % of FTP targets.
+fn power_target(step: &WorkoutStep, ftp: Option<f64>) -> Option<(f64, f64)> {
+    if step.target_type != "power" {
+        return None;
+    }
+    let watts = |value: f64| {
+        if value > 1000.0 {
+            Some(value - 1000.0)
+        } else {
+            ftp.map(|ftp| value * ftp / 100.0)
+        }
+    };
+    let low = watts(step.custom_target_power_low?)?;
+    let high = watts(step.custom_target_power_high?)?;
+    (high > 0.0).then_some((low.min(high), low.max(high)))
+}
+
+fn power_compliance(actual: f64, (low, high): (f64, f64)) -> f64 {
+    if actual < low {
+        actual / low
+    } else if actual > high {
+        high / actual
+    } else {
+        1.0
+    }
+}
+
+fn step_compliance(
+    step: &WorkoutStep,
+    repetition: usize,
+    lap: Option<&LapStats>,
+    ftp: Option<f64>,
+) -> StepCompliance {
+    let planned_duration = planned_duration(step);
+    let actual_duration = lap.and_then(|lap| lap.timer_time).unwrap_or_default();
+    let target = power_target(step, ftp);
+    let actual_power = lap.and_then(|lap| lap.avg_power.or(lap.avg_power_excluding_zeros));
+
+    let duration_compliance = planned_duration.map(|planned| (actual_duration / planned).min(1.0));
+    let power_compliance = match (lap, target) {
+        (Some(_), Some(target)) => Some(power_compliance(actual_power.unwrap_or_default(), target)),
+        (None, Some(_)) => Some(0.0),
+        _ => None,
+    };
+    let components: Vec<f64> = duration_compliance
+        .into_iter()
+        .chain(power_compliance)
+        .collect();
+
+    StepCompliance {
+        step_index: step.message_index,
+        repetition,
+        name: step.wkt_step_name.clone(),
+        intensity: Some(step.intensity.clone()).filter(|i| !i.is_empty()),
+        lap_index: lap.map(|lap| lap.index),
+        planned_duration,
+        actual_duration,
+        duration_compliance,
+        target_power_low: target.map(|(low, _)| low),
+        target_power_high: target.map(|(_, high)| high),
+        actual_power,
+        power_compliance,
+        score: (!components.is_empty())
+            .then(|| components.iter().sum::<f64>() / components.len() as f64),
+    }
+}
+
+fn planned_duration(step: &WorkoutStep) -> Option<f64> {
+    (step.duration_type == "time")
+        .then_some(step.duration_time.value)
+        .filter(|duration| *duration > 0.0)
+}
+
+/// Lays the timed steps back to back from the first `power` sample and
+/// summarises the samples within each, for rides without per-step laps.
+/// Steps after the first one without a fixed duration can't be placed.
+fn time_ranges(planned: &[(&WorkoutStep, usize)], power: &[(i64, f64)]) -> Vec<Option<LapStats>> {
+    let (Some(&(first, _)), Some(&(last, _))) = (power.first(), power.last()) else {
+        return vec![None; planned.len()];
+    };
+    let mut sorted = power.to_vec();
+    sorted.sort_by_key(|&(timestamp, _)| timestamp);
+    let mut samples = sorted.into_iter().peekable();
+    let mut start = first;
+    let mut ranges = Vec::new();
+    for (step, _) in planned {
+        let Some(duration) = planned_duration(step) else {
+            break;
+        };
+        let end = start + duration.round() as i64;
+        // the ranges follow each other, so every sample is visited once
+        let mut watts = Vec::new();
+        while let Some((_, w)) = samples.next_if(|&(timestamp, _)| timestamp < end) {
+            watts.push(w);
+        }
+        ranges.push((start <= last).then(|| LapStats {
+            timer_time: Some((end.min(last + 1) - start) as f64),
+            avg_power: (!watts.is_empty()).then(|| watts.iter().sum::<f64>() / watts.len() as f64),
+            ..Default::default()
+        }));
+        start = end;
+    }
+    ranges.resize(planned.len(), None);
+    ranges
+}
+
+/// Matches the executed `laps` against the planned workout `steps`. Laps are
+/// paired with the next planned pass of the step they were recorded for, so
+/// steps the athlete skipped show up without a lap. When no lap records its
+/// step, the plan is matched against time ranges of the `power` samples.
+pub fn score_compliance(
+    workout_name: Option<String>,
+    steps: &[WorkoutStep],
+    laps: &[LapStats],
+    power: &[(i64, f64)],
+    ftp: Option<f64>,
+) -> Option<ComplianceReport> {
+    let planned = unroll(steps);
+    if planned.is_empty() {
+        return None;
+    }
+
+    let by_lap = laps.iter().any(|lap| lap.wkt_step_index.is_some());
+    let matched = if by_lap {
+        let mut matched: Vec<Option<LapStats>> = vec![None; planned.len()];
+        let mut cursor = 0;
+        for lap in laps {
+            let Some(step_index) = lap.wkt_step_index else {
+                continue;
+            };
+            if let Some(offset) = planned[cursor..]
+                .iter()
+                .position(|(step, _)| step.message_index == step_index)
+            {
+                matched[cursor + offset] = Some(lap.clone());
+                cursor += offset + 1;
+            }
+        }
+        matched
+    } else {
+        time_ranges(&planned, power)
+    };
+
+    let steps: Vec<StepCompliance> = planned
+        .iter()
+        .zip(&matched)
+        .map(|(&(step, repetition), lap)| {
+            let mut compliance = step_compliance(step, repetition, lap.as_ref(), ftp);
+            if !by_lap {
+                compliance.lap_index = None;
+            }
+            compliance
+        })
+        .collect();
+
+    let weight = |step: &StepCompliance| step.planned_duration.unwrap_or(step.actual_duration);
+    let total_weight: f64 = steps.iter().filter(|s| s.score.is_some()).map(weight).sum();
+    let score = (total_weight > 0.0).then(|| {
+        steps
+            .iter()
+            .filter_map(|s| Some(s.score? * weight(s)))
+            .sum::<f64>()
+            / total_weight
+    });
+
+    Some(ComplianceReport {
+        workout_name,
+        planned_duration: steps.iter().filter_map(|s| s.planned_duration).sum(),
+        actual_duration: steps.iter().map(|s| s.actual_duration).sum(),
+        steps,
+        score,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(index: i64, seconds: f64, watts: Option<(f64, f64)>) -> WorkoutStep {
+        WorkoutStep {
+            duration_time: (seconds, "s").into(),
+            duration_type: "time".to_owned(),
+            intensity: "active".to_owned(),
+            message_index: index,
+            target_type: if watts.is_some() { "power" } else { "open" }.to_owned(),
+            target_value: 0,
+            wkt_step_name: None,
+            custom_target_power_low: watts.map(|(low, _)| low),
+            custom_target_power_high: watts.map(|(_, high)| high),
+            duration_step: None,
+            repeat_steps: None,
+        }
+    }
+
+    fn repeat(index: i64, from: i64, times: i64) -> WorkoutStep {
+        WorkoutStep {
+            duration_type: "repeat_until_steps_cmplt".to_owned(),
+            target_type: String::new(),
+            duration_step: Some(from),
+            repeat_steps: Some(times),
+            ..step(index, 0.0, None)
+        }
+    }
+
+    fn lap(index: i64, step: i64, seconds: f64, watts: f64) -> LapStats {
+        LapStats {
+            index,
+            wkt_step_index: Some(step),
+            timer_time: Some(seconds),
+            avg_power: Some(watts),
+            ..Default::default()
+        }
+    }
+
+    /// Warm-up, then 2 × (work, rest).
+    fn plan() -> Vec<WorkoutStep> {
+        vec![
+            step(0, 600.0, None),
+            step(1, 300.0, Some((1270.0, 1300.0))),
+            step(2, 120.0, Some((50.0, 60.0))),
+            repeat(3, 1, 2),
+        ]
+    }
+
+    #[test]
+    fn unrolls_repeated_blocks() {
+        let steps = plan();
+        let planned: Vec<(i64, usize)> = unroll(&steps)
+            .iter()
+            .map(|(step, repetition)| (step.message_index, *repetition))
+            .collect();
+        assert_eq!(planned, vec![(0, 1), (1, 1), (2, 1), (1, 2), (2, 2)]);
+    }
+
+    #[test]
+    fn resolves_power_targets() {
+        let absolute = step(1, 300.0, Some((1270.0, 1300.0)));
+        assert_eq!(power_target(&absolute, None), Some((270.0, 300.0)));
+        let relative = step(2, 120.0, Some((50.0, 60.0)));
+        assert_eq!(power_target(&relative, Some(250.0)), Some((125.0, 150.0)));
+        assert_eq!(power_target(&relative, None), None);
+        assert_eq!(power_target(&step(0, 600.0, None), Some(250.0)), None);
+    }
+
+    #[test]
+    fn scores_laps_against_the_plan() {
+        let laps = vec![
+            lap(0, 0, 600.0, 150.0),
+            lap(1, 1, 300.0, 285.0),
+            lap(2, 2, 120.0, 140.0),
+            lap(3, 1, 150.0, 285.0),
+        ];
+        let report = score_compliance(None, &plan(), &laps, &[], Some(250.0)).unwrap();
+        assert_eq!(report.steps.len(), 5);
+        assert_eq!(report.steps[1].score, Some(1.0));
+        // half the planned duration at the target
+        assert_eq!(report.steps[3].repetition, 2);
+        assert_eq!(report.steps[3].score, Some(0.75));
+        // the last rest was skipped
+        assert_eq!(report.steps[4].lap_index, None);
+        assert_eq!(report.steps[4].score, Some(0.0));
+        let score = report.score.unwrap();
+        assert!(score > 0.5 && score < 1.0);
+        assert_eq!(report.planned_duration, 1440.0);
+    }
+
+    #[test]
+    fn matches_time_ranges_without_step_laps() {
+        let power: Vec<(i64, f64)> = (0..900)
+            .map(|t| (t, if t < 600 { 150.0 } else { 285.0 }))
+            .collect();
+        let steps = vec![step(0, 600.0, None), step(1, 300.0, Some((1270.0, 1300.0)))];
+        let report = score_compliance(None, &steps, &[], &power, None).unwrap();
+        assert_eq!(report.steps[1].actual_power, Some(285.0));
+        assert_eq!(report.steps[1].score, Some(1.0));
+        assert!(report.steps.iter().all(|s| s.lap_index.is_none()));
+    }
+
+    #[test]
+    fn caps_repetitions_from_the_file() {
+        let steps = vec![step(0, 60.0, None), repeat(1, 0, u32::MAX as i64)];
+        assert_eq!(unroll(&steps).len(), MAX_REPETITIONS);
+
+        let mut steps: Vec<WorkoutStep> = (0..500).map(|i| step(i, 60.0, None)).collect();
+        steps.push(repeat(500, 0, 1000));
+        assert_eq!(unroll(&steps).len(), MAX_PLANNED_STEPS);
+    }
+
+    #[test]
+    fn no_plan_no_report() {
+        assert!(score_compliance(None, &[], &[], &[], None).is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+
+use crate::power_curve::{power_curve_buckets, MeanMaxCurve};
+
+/// Durations (seconds) of the power curve the models are fitted over.
+const FIT_MIN_DURATION: usize = 120;
+const FIT_MAX_DURATION: usize = 1200;
+
+/// Range and resolution of the Morton `k` grid search, in seconds.
+const MORTON_K_MIN: f64 = -300.0;
+const MORTON_K_STEP: f64 = 0.5;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CriticalPowerModel {
+    /// `P = W' / t + CP`, fitted as the linear work-time relationship.
+    #[default]
+    TwoParameter,
+    /// Morton's `P = W' / (t - k) + CP`.
+    ThreeParameter,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CriticalPowerFit {
+    pub model: CriticalPowerModel,
+    /// Critical power in watts.
+    pub cp: f64,
+    /// Work capacity above CP in joules.
+    pub w_prime: f64,
+    /// Morton's time asymptote in seconds, only for the 3-parameter model.
+    pub k: Option<f64>,
+    pub rmse: f64,
+    pub r_squared: f64,
+    /// `(duration, actual - modeled power)` for every point used in the fit.
+    pub residuals: Vec<(usize, f64)>,
+    pub modeled_curve: MeanMaxCurve,
+}
+
+impl CriticalPowerFit {
+    pub fn power_at(&self, duration: f64) -> f64 {
+        self.w_prime / (duration - self.k.unwrap_or_default()) + self.cp
+    }
+}
+
+/// Least-squares `(slope, intercept)` of `y` over `x`.
+fn linear_regression(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let sxx: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    if points.len() < 2 || sxx == 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    Some((slope, mean_y - slope * mean_x))
+}
+
+/// `(cp, w_prime)` of `P = W' / (t - k) + CP` for a fixed `k`.
+fn fit_hyperbola(points: &[(f64, f64)], k: f64) -> Option<(f64, f64)> {
+    let transformed: Vec<(f64, f64)> = points.iter().map(|&(t, p)| (1.0 / (t - k), p)).collect();
+    linear_regression(&transformed).map(|(w_prime, cp)| (cp, w_prime))
+}
+
+fn sum_squared_error(points: &[(f64, f64)], cp: f64, w_prime: f64, k: f64) -> f64 {
+    points
+        .iter()
+        .map(|&(t, p)| (p - (w_prime / (t - k) + cp)).powi(2))
+        .sum()
+}
+
+/// Fits `model` to the 2–20 minute range of a mean-max power curve. Returns
+/// `None` when the curve doesn't cover enough of that range or the fit is
+/// physiologically meaningless.
+pub fn fit_critical_power(
+    curve: &MeanMaxCurve,
+    model: CriticalPowerModel,
+) -> Option<CriticalPowerFit> {
+    let points: Vec<(f64, f64)> = curve
+        .iter()
+        .filter(|&&(d, p)| (FIT_MIN_DURATION..=FIT_MAX_DURATION).contains(&d) && p > 0.0)
+        .map(|&(d, p)| (d as f64, p as f64))
+        .collect();
+
+    let (cp, w_prime, k) = match model {
+        CriticalPowerModel::TwoParameter => {
+            let work: Vec<(f64, f64)> = points.iter().map(|&(t, p)| (t, p * t)).collect();
+            let (cp, w_prime) = linear_regression(&work)?;
+            (cp, w_prime, None)
+        }
+        CriticalPowerModel::ThreeParameter => {
+            let steps = (-MORTON_K_MIN / MORTON_K_STEP) as usize;
+            let (cp, w_prime, k, _) = (0..=steps)
+                .map(|i| MORTON_K_MIN + i as f64 * MORTON_K_STEP)
+                .filter_map(|k| {
+                    let (cp, w_prime) = fit_hyperbola(&points, k)?;
+                    // an invalid best k mustn't hide a valid, slightly worse one
+                    (cp > 0.0 && w_prime > 0.0)
+                        .then(|| (cp, w_prime, k, sum_squared_error(&points, cp, w_prime, k)))
+                })
+                .min_by(|a, b| a.3.total_cmp(&b.3))?;
+            (cp, w_prime, Some(k))
+        }
+    };
+    if cp <= 0.0 || w_prime <= 0.0 {
+        return None;
+    }
+
+    let mut fit = CriticalPowerFit {
+        model,
+        cp,
+        w_prime,
+        k,
+        rmse: 0.0,
+        r_squared: 0.0,
+        residuals: Vec::new(),
+        modeled_curve: Vec::new(),
+    };
+    fit.residuals = points
+        .iter()
+        .map(|&(t, p)| (t as usize, p - fit.power_at(t)))
+        .collect();
+    let sse: f64 = fit.residuals.iter().map(|r| r.1.powi(2)).sum();
+    let mean = points.iter().map(|p| p.1).sum::<f64>() / points.len() as f64;
+    let sst: f64 = points.iter().map(|p| (p.1 - mean).powi(2)).sum();
+    fit.rmse = (sse / points.len() as f64).sqrt();
+    fit.r_squared = if sst > 0.0 { 1.0 - sse / sst } else { 1.0 };
+    fit.modeled_curve = power_curve_buckets()
+        .iter()
+        .map(|&d| (d, fit.power_at(d as f64) as f32))
+        .collect();
+    Some(fit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn synthetic_curve(cp: f64, w_prime: f64, k: f64) -> MeanMaxCurve {
+        power_curve_buckets()
+            .iter()
+            .map(|&d| (d, (w_prime / (d as f64 - k) + cp) as f32))
+            .collect()
+    }
+
+    #[test]
+    fn recovers_two_parameter_model() {
+        let curve = synthetic_curve(250.0, 20_000.0, 0.0);
+        let fit = fit_critical_power(&curve, CriticalPowerModel::TwoParameter).unwrap();
+        assert!((fit.cp - 250.0).abs() < 0.5, "cp {}", fit.cp);
+        assert!((fit.w_prime - 20_000.0).abs() < 100.0, "w' {}", fit.w_prime);
+        assert!(fit.r_squared > 0.999);
+        assert_eq!(fit.k, None);
+    }
+
+    #[test]
+    fn recovers_three_parameter_model() {
+        let curve = synthetic_curve(250.0, 20_000.0, -20.0);
+        let fit = fit_critical_power(&curve, CriticalPowerModel::ThreeParameter).unwrap();
+        assert!((fit.cp - 250.0).abs() < 0.5, "cp {}", fit.cp);
+        assert!((fit.w_prime - 20_000.0).abs() < 100.0, "w' {}", fit.w_prime);
+        assert!((fit.k.unwrap() + 20.0).abs() <= MORTON_K_STEP);
+    }
+
+    #[test]
+    fn needs_the_fitting_range() {
+        let curve: MeanMaxCurve = vec![(5, 900.0), (30, 600.0), (60, 450.0)];
+        assert!(fit_critical_power(&curve, CriticalPowerModel::TwoParameter).is_none());
+        assert!(fit_critical_power(&curve, CriticalPowerModel::ThreeParameter).is_none());
+    }
+}
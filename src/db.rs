@@ -1,10 +1,20 @@
-use bson::Document;
+use bson::{doc, Document};
 use dotenv::dotenv;
-use mongodb::{options::ClientOptions, Client, Collection};
+use mongodb::{
+    error::{Error, ErrorKind, WriteFailure},
+    options::{ClientOptions, IndexOptions},
+    Client, Collection, IndexModel,
+};
+
+/// Server error code of a write violating a unique index.
+const DUPLICATE_KEY: i32 = 11000;
 
 #[derive(Clone, Debug)]
 pub struct DB {
     pub collection: Collection<Document>,
+    pub personal_records: Collection<Document>,
+    pub users: Collection<Document>,
+    pub streams: Collection<Document>,
 }
 
 // type Result<T> = std::result::Result<T, MyError>;
@@ -25,9 +35,59 @@ impl DB {
         let database = client.database(database_name.as_str());
 
         let collection = database.collection::<Document>(collection_name.as_str());
+        let personal_records = database.collection::<Document>("personal_records");
+        let users = database.collection::<Document>("users");
+        let streams = database.collection::<Document>("streams");
 
         println!("✅ Database connected successfully");
 
-        Ok(Self { collection })
+        let db = Self {
+            collection,
+            personal_records,
+            users,
+            streams,
+        };
+        // existing duplicates keep the indexes from being built, which only
+        // leaves concurrent uploads unguarded
+        if let Err(e) = db.create_indexes().await {
+            eprintln!("Error creating indexes {e:?}");
+        }
+        Ok(db)
+    }
+
+    /// Indexes for how the collections are queried, and unique ones on the
+    /// fields duplicate uploads are detected by, so concurrent uploads of the
+    /// same file can't both be stored.
+    async fn create_indexes(&self) -> Result<(), Error> {
+        let streams = IndexModel::builder()
+            .keys(doc! { "activity_id": 1, "chunk": 1 })
+            .build();
+        self.streams.create_index(streams, None).await?;
+        let personal_records = IndexModel::builder()
+            .keys(doc! { "user_id": 1, "start_time": -1 })
+            .build();
+        self.personal_records
+            .create_index(personal_records, None)
+            .await?;
+        for field in ["content_hash", "file_fingerprint"] {
+            let options = IndexOptions::builder()
+                .unique(true)
+                .partial_filter_expression(doc! { field: { "$type": "string" } })
+                .build();
+            let index = IndexModel::builder()
+                .keys(doc! { "user_id": 1, field: 1 })
+                .options(options)
+                .build();
+            self.collection.create_index(index, None).await?;
+        }
+        Ok(())
     }
 }
+
+/// Whether `error` is a write rejected by a unique index.
+pub fn is_duplicate_key(error: &Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == DUPLICATE_KEY
+    )
+}
@@ -0,0 +1,278 @@
+use axum::{
+    extract::multipart::MultipartError,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+/// Everything a handler can fail with. Responds with a JSON body of the
+/// form `{code, message, details}`.
+#[derive(Debug)]
+pub enum AppError {
+    /// The upload isn't valid multipart or the file isn't a readable FIT file.
+    Parse {
+        message: String,
+        details: String,
+    },
+    /// The upload has no files.
+    MissingFile,
+    /// A parameter is malformed or inconsistent.
+    Validation(String),
+    /// The upload exceeds the request body limit.
+    PayloadTooLarge(String),
+    NotFound(String),
+    /// The stored data doesn't allow computing what was asked for.
+    InsufficientData(String),
+    Database(mongodb::error::Error),
+    Serialization(String),
+    /// A bug on our side, such as a panicked task.
+    Internal(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    code: &'static str,
+    message: String,
+    details: Option<String>,
+}
+
+impl AppError {
+    pub fn not_found(what: &str) -> Self {
+        AppError::NotFound(format!("{what} not found"))
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::Parse { .. } | AppError::MissingFile | AppError::Validation(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::InsufficientData(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Database(_) | AppError::Serialization(_) | AppError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// The JSON body of the error, logging what it doesn't reveal.
+    pub fn into_body(self) -> ErrorBody {
+        match &self {
+            AppError::Database(e) => eprintln!("Error accessing db {e:?}"),
+            AppError::Serialization(e) => eprintln!("Error converting bson {e}"),
+            AppError::Internal(e) => eprintln!("Internal error {e}"),
+            _ => {}
+        }
+        let (code, message, details) = match self {
+            AppError::Parse { message, details } => ("parse_error", message, Some(details)),
+            AppError::MissingFile => ("missing_file", "The upload has no files".to_owned(), None),
+            AppError::Validation(message) => ("validation_error", message, None),
+            AppError::PayloadTooLarge(message) => ("payload_too_large", message, None),
+            AppError::NotFound(message) => ("not_found", message, None),
+            AppError::InsufficientData(message) => ("insufficient_data", message, None),
+            // internals stay in the logs
+            AppError::Database(_) => ("database_error", "Database request failed".to_owned(), None),
+            AppError::Serialization(_) => (
+                "serialization_error",
+                "Failed to convert stored data".to_owned(),
+                None,
+            ),
+            AppError::Internal(_) => ("internal_error", "Internal server error".to_owned(), None),
+        };
+        ErrorBody {
+            code,
+            message,
+            details,
+        }
+    }
+}
+
+/// Rejects a `name` parameter that is set but not positive.
+pub fn ensure_positive(name: &str, value: Option<f64>) -> Result<(), AppError> {
+    match value {
+        Some(value) if value <= 0.0 || !value.is_finite() => Err(AppError::Validation(format!(
+            "{name} must be a positive number, got {value}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        (status, Json(self.into_body())).into_response()
+    }
+}
+
+impl From<mongodb::error::Error> for AppError {
+    fn from(e: mongodb::error::Error) -> Self {
+        AppError::Database(e)
+    }
+}
+
+impl From<bson::ser::Error> for AppError {
+    fn from(e: bson::ser::Error) -> Self {
+        AppError::Serialization(e.to_string())
+    }
+}
+
+impl From<bson::de::Error> for AppError {
+    fn from(e: bson::de::Error) -> Self {
+        AppError::Serialization(e.to_string())
+    }
+}
+
+impl From<MultipartError> for AppError {
+    fn from(e: MultipartError) -> Self {
+        match e.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => AppError::PayloadTooLarge(e.body_text()),
+            status if status.is_server_error() => AppError::Internal(e.body_text()),
+            _ => AppError::Parse {
+                message: "Malformed multipart upload".to_owned(),
+                details: e.body_text(),
+            },
+        }
+    }
+}
+
+impl From<fitparser::Error> for AppError {
+    fn from(e: fitparser::Error) -> Self {
+        AppError::Parse {
+            message: "The file is not a valid FIT file".to_owned(),
+            details: e.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    async fn respond(error: AppError) -> (StatusCode, Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn errors_respond_with_status_and_json_body() {
+        let cases = [
+            (
+                AppError::Parse {
+                    message: "bad.fit is not a valid FIT file".to_owned(),
+                    details: "unexpected end of file".to_owned(),
+                },
+                StatusCode::BAD_REQUEST,
+                json!({
+                    "code": "parse_error",
+                    "message": "bad.fit is not a valid FIT file",
+                    "details": "unexpected end of file",
+                }),
+            ),
+            (
+                AppError::MissingFile,
+                StatusCode::BAD_REQUEST,
+                json!({ "code": "missing_file", "message": "The upload has no files", "details": null }),
+            ),
+            (
+                AppError::Validation("ftp must be a positive number, got 0".to_owned()),
+                StatusCode::BAD_REQUEST,
+                json!({
+                    "code": "validation_error",
+                    "message": "ftp must be a positive number, got 0",
+                    "details": null,
+                }),
+            ),
+            (
+                AppError::PayloadTooLarge("Request payload is too large".to_owned()),
+                StatusCode::PAYLOAD_TOO_LARGE,
+                json!({
+                    "code": "payload_too_large",
+                    "message": "Request payload is too large",
+                    "details": null,
+                }),
+            ),
+            (
+                AppError::not_found("Activity"),
+                StatusCode::NOT_FOUND,
+                json!({ "code": "not_found", "message": "Activity not found", "details": null }),
+            ),
+            (
+                AppError::InsufficientData("No power data".to_owned()),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                json!({ "code": "insufficient_data", "message": "No power data", "details": null }),
+            ),
+            (
+                AppError::Database(mongodb::error::Error::custom("connection reset")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({
+                    "code": "database_error",
+                    "message": "Database request failed",
+                    "details": null,
+                }),
+            ),
+            (
+                AppError::Serialization("missing field `summary`".to_owned()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({
+                    "code": "serialization_error",
+                    "message": "Failed to convert stored data",
+                    "details": null,
+                }),
+            ),
+            (
+                AppError::Internal("task panicked".to_owned()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({
+                    "code": "internal_error",
+                    "message": "Internal server error",
+                    "details": null,
+                }),
+            ),
+        ];
+        for (error, status, body) in cases {
+            assert_eq!(respond(error).await, (status, body));
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_multipart_uploads_are_payload_too_large() {
+        use axum::{
+            body::Body,
+            extract::{FromRequest, Multipart},
+            http::Request,
+        };
+
+        // beyond axum's default body limit of 2 MB
+        let body = format!(
+            "--X\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.fit\"\r\n\r\n{}\r\n--X--\r\n",
+            "a".repeat(3 * 1024 * 1024)
+        );
+        let request = Request::builder()
+            .header("content-type", "multipart/form-data; boundary=X")
+            .body(Body::from(body))
+            .unwrap();
+        let mut multipart = Multipart::from_request(request, &()).await.unwrap();
+        let read = async { multipart.next_field().await?.unwrap().bytes().await };
+        let error = AppError::from(read.await.unwrap_err());
+        assert!(matches!(error, AppError::PayloadTooLarge(_)));
+        assert_eq!(respond(error).await.0, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn ensure_positive_rejects_zero_negative_and_non_finite() {
+        assert!(ensure_positive("ftp", None).is_ok());
+        assert!(ensure_positive("ftp", Some(250.0)).is_ok());
+        for value in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                ensure_positive("ftp", Some(value)),
+                Err(AppError::Validation(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::error::AppError;
+
+/// `axum::extract::Query` rejecting with an `AppError`, so a malformed query
+/// string gets the same JSON error body as every other failure.
+#[derive(axum_macros::FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+pub struct Query<T>(pub T);
+
+/// `axum::extract::Path` rejecting with an `AppError`.
+#[derive(axum_macros::FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(AppError))]
+pub struct Path<T>(pub T);
+
+/// `axum::Json` rejecting with an `AppError`. Responds like `axum::Json`.
+#[derive(axum_macros::FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::Validation(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        AppError::Validation(rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::Validation(rejection.body_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        extract::{FromRequest, FromRequestParts},
+        http::Request,
+    };
+    use chrono::NaiveDate;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::analysis::AnalysisOptions;
+    use crate::handlers::UploadOptions;
+    use crate::users::AthleteSettings;
+
+    #[derive(Debug, Deserialize)]
+    struct DateQuery {
+        #[allow(dead_code)]
+        from: Option<NaiveDate>,
+    }
+
+    async fn query<T: serde::de::DeserializeOwned>(uri: &str) -> Result<T, AppError> {
+        let (mut parts, _) = Request::builder().uri(uri).body(()).unwrap().into_parts();
+        Query::<T>::from_request_parts(&mut parts, &())
+            .await
+            .map(|Query(value)| value)
+    }
+
+    fn is_validation(result: Result<impl Sized, AppError>) -> bool {
+        matches!(result, Err(AppError::Validation(_)))
+    }
+
+    #[tokio::test]
+    async fn malformed_query_strings_are_validation_errors() {
+        assert!(is_validation(query::<DateQuery>("/?from=yesterday").await));
+        assert!(query::<DateQuery>("/?from=2024-03-01").await.is_ok());
+        assert!(is_validation(
+            query::<AnalysisOptions>("/?power_zones=100,fast").await
+        ));
+        assert!(is_validation(query::<UploadOptions>("/?force=maybe").await));
+    }
+
+    #[tokio::test]
+    async fn malformed_json_bodies_are_validation_errors() {
+        let request = Request::builder()
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"effective_from": "2024-03-01T00:00:00Z", "ftp": "high"}"#,
+            ))
+            .unwrap();
+        let result = Json::<AthleteSettings>::from_request(request, &()).await;
+        assert!(is_validation(result.map(|Json(settings)| settings)));
+    }
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+use crate::critical_power::{fit_critical_power, CriticalPowerModel};
+use crate::power_curve::MeanMaxCurve;
+
+/// Days of rides the estimate is based on unless asked otherwise.
+pub const DEFAULT_ESTIMATE_DAYS: i64 = 42;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FtpMethod {
+    /// 95% of the best 20 minute power.
+    #[default]
+    TwentyMinute,
+    /// Critical power of the 2-parameter model.
+    CriticalPower,
+    /// 90% of the best 8 minute power.
+    EightMinute,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FtpEstimate {
+    pub method: FtpMethod,
+    pub ftp: f64,
+}
+
+fn scaled_best(curve: &MeanMaxCurve, duration: usize, factor: f64) -> Option<f64> {
+    curve
+        .iter()
+        .find(|&&(d, _)| d == duration)
+        .map(|&(_, power)| power as f64 * factor)
+        .filter(|ftp| *ftp > 0.0)
+}
+
+/// Estimates FTP from a mean-max power curve, `None` when the curve is too
+/// short for `method`.
+pub fn estimate_ftp(curve: &MeanMaxCurve, method: FtpMethod) -> Option<FtpEstimate> {
+    let ftp = match method {
+        FtpMethod::TwentyMinute => scaled_best(curve, 1200, 0.95),
+        FtpMethod::CriticalPower => {
+            fit_critical_power(curve, CriticalPowerModel::TwoParameter).map(|fit| fit.cp)
+        }
+        FtpMethod::EightMinute => scaled_best(curve, 480, 0.90),
+    }?;
+    Some(FtpEstimate {
+        method,
+        ftp: ftp.round(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power_curve::power_curve_buckets;
+
+    fn curve() -> MeanMaxCurve {
+        // CP 250 W, W′ 18 kJ
+        power_curve_buckets()
+            .iter()
+            .map(|&d| (d, (18_000.0 / d as f64 + 250.0) as f32))
+            .collect()
+    }
+
+    #[test]
+    fn estimates_from_best_efforts() {
+        let twenty = estimate_ftp(&curve(), FtpMethod::TwentyMinute).unwrap();
+        assert_eq!(twenty.ftp, 252.0); // 0.95 × 265 W
+        let eight = estimate_ftp(&curve(), FtpMethod::EightMinute).unwrap();
+        assert_eq!(eight.ftp, 259.0); // 0.90 × 287.5 W
+        let cp = estimate_ftp(&curve(), FtpMethod::CriticalPower).unwrap();
+        assert_eq!(cp.ftp, 250.0);
+        assert_eq!(cp.method, FtpMethod::CriticalPower);
+    }
+
+    #[test]
+    fn short_curves_have_no_estimate() {
+        let short: MeanMaxCurve = vec![(5, 900.0), (60, 450.0), (300, 320.0)];
+        for method in [
+            FtpMethod::TwentyMinute,
+            FtpMethod::CriticalPower,
+            FtpMethod::EightMinute,
+        ] {
+            assert!(estimate_ftp(&short, method).is_none());
+        }
+    }
+}
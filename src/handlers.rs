@@ -0,0 +1,1093 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    extract::{Multipart, State},
+    http::StatusCode,
+};
+use bson::{doc, from_document, oid::ObjectId, to_bson, to_document, Bson, Document};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use mongodb::options::{
+    FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReplaceOptions, ReturnDocument,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::AnalysisOptions;
+use crate::compliance::ComplianceReport;
+use crate::critical_power::{fit_critical_power, CriticalPowerFit, CriticalPowerModel};
+use crate::db::is_duplicate_key;
+use crate::error::{AppError, ErrorBody};
+use crate::extract::{Json, Path, Query};
+use crate::ftp::{estimate_ftp, FtpEstimate, FtpMethod, DEFAULT_ESTIMATE_DAYS};
+use crate::laps::LapStats;
+use crate::metrics::ActivityMetrics;
+use crate::pmc::{calculate_pmc, PmcDay, MAX_PMC_DAYS, MAX_WARMUP_DAYS};
+use crate::power_curve::{merge_mean_max_curves, MeanMaxCurve};
+use crate::records::{detect_personal_records, PersonalRecord, RecordPeriod, RECORD_DURATIONS};
+use crate::streams::RecordStreams;
+use crate::structures::{MongoSchema, Split};
+use crate::summary::ActivitySummary;
+use crate::upload::{analyse_files, expand_archive, UnpackBudget, UploadedFile};
+use crate::users::{AthleteSettings, UserProfile};
+use crate::AppState;
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// `date` moved by `days`, rejected when that leaves the dates chrono supports.
+fn shift_days(date: NaiveDate, days: i64) -> Result<NaiveDate, AppError> {
+    date.checked_add_signed(Duration::days(days))
+        .ok_or_else(|| AppError::Validation(format!("{date} is out of range")))
+}
+
+/// Filter for a user's activities, optionally restricted to a sport and to
+/// start dates within `[from, to]` (both inclusive, UTC days).
+fn activities_filter(
+    user_id: &str,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    sport: Option<&str>,
+) -> Result<Document, AppError> {
+    let mut filter = doc! { "user_id": user_id };
+    let mut start_time = Document::new();
+    if let Some(from) = from {
+        let from: DateTime<Utc> = from.and_hms_opt(0, 0, 0).unwrap().and_utc();
+        start_time.insert("$gte", to_bson(&from)?);
+    }
+    if let Some(to) = to {
+        let to: DateTime<Utc> = shift_days(to, 1)?.and_hms_opt(0, 0, 0).unwrap().and_utc();
+        start_time.insert("$lt", to_bson(&to)?);
+    }
+    if !start_time.is_empty() {
+        filter.insert("summary.start_time", start_time);
+    }
+    if let Some(sport) = sport {
+        filter.insert("summary.sport", sport);
+    }
+    Ok(filter)
+}
+
+/// What the client needs to show a freshly uploaded activity without
+/// fetching it again.
+#[derive(Debug, Serialize)]
+pub struct UploadedActivity {
+    id: String,
+    #[serde(flatten)]
+    summary: ActivitySummary,
+    record_count: usize,
+    metrics: ActivityMetrics,
+    power_curve: MeanMaxCurve,
+}
+
+/// What became of one uploaded file.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FileStatus {
+    Created {
+        id: String,
+    },
+    /// The user already has this activity, stored as `existing_id`.
+    Duplicate {
+        existing_id: String,
+    },
+    Error {
+        error: ErrorBody,
+    },
+}
+
+impl FileStatus {
+    fn failed(error: AppError) -> Self {
+        FileStatus::Error {
+            error: error.into_body(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileResult {
+    file_name: String,
+    #[serde(flatten)]
+    status: FileStatus,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadResponse {
+    message: String,
+    activity_ids: Vec<String>,
+    activities: Vec<UploadedActivity>,
+    personal_records: Vec<PersonalRecord>,
+    /// Set when the upload raised the profile's FTP.
+    updated_ftp: Option<FtpEstimate>,
+    /// One entry per uploaded file, in upload order.
+    files: Vec<FileResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredPowerCurve {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    #[serde(default)]
+    summary: ActivitySummary,
+    #[serde(default)]
+    power_curve: Vec<(usize, f32)>,
+    #[serde(default)]
+    power_curve_wkg: Option<MeanMaxCurve>,
+}
+
+async fn load_power_curves(
+    app_state: &AppState,
+    filter: Document,
+) -> Result<Vec<StoredPowerCurve>, AppError> {
+    let projection = doc! { "summary.start_time": 1, "power_curve": 1, "power_curve_wkg": 1 };
+    find_power_curves(app_state, filter, projection).await
+}
+
+/// `load_power_curves` with only the `RECORD_DURATIONS` points of each curve,
+/// which is all personal record detection compares.
+async fn load_record_curves(
+    app_state: &AppState,
+    filter: Document,
+) -> Result<Vec<StoredPowerCurve>, AppError> {
+    let durations: Vec<i64> = RECORD_DURATIONS.iter().map(|&d| d as i64).collect();
+    let projection = doc! {
+        "summary.start_time": 1,
+        "power_curve": {
+            "$filter": {
+                "input": "$power_curve",
+                "as": "point",
+                "cond": { "$in": [{ "$arrayElemAt": ["$$point", 0] }, durations] },
+            },
+        },
+    };
+    find_power_curves(app_state, filter, projection).await
+}
+
+async fn find_power_curves(
+    app_state: &AppState,
+    filter: Document,
+    projection: Document,
+) -> Result<Vec<StoredPowerCurve>, AppError> {
+    let options = FindOptions::builder().projection(projection).build();
+    let mut cursor = app_state.db.collection.find(filter, options).await?;
+    let mut curves = Vec::new();
+    while cursor.advance().await? {
+        curves.push(from_document(cursor.deserialize_current()?)?);
+    }
+    Ok(curves)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadOptions {
+    /// Store activities even when they duplicate stored ones.
+    #[serde(default)]
+    force: bool,
+}
+
+/// The stored activity of `user_id` with the same file contents as
+/// `mongo_doc`, or recorded by the same device at the same time. Activities
+/// stored before `file_fingerprint` existed are matched on their `file_id`.
+async fn find_duplicate(
+    app_state: &AppState,
+    user_id: &str,
+    mongo_doc: &MongoSchema,
+) -> Result<Option<ObjectId>, AppError> {
+    let mut matches = Vec::new();
+    if let Some(content_hash) = &mongo_doc.content_hash {
+        matches.push(doc! { "content_hash": content_hash });
+    }
+    if let Some(fingerprint) = &mongo_doc.file_fingerprint {
+        matches.push(doc! { "file_fingerprint": fingerprint });
+        if let Some(file_id) = &mongo_doc.activity.file_id {
+            matches.push(doc! {
+                "file_fingerprint": { "$exists": false },
+                "activity.file_id.manufacturer": &file_id.manufacturer,
+                "activity.file_id.serial_number": to_bson(&file_id.serial_number)?,
+                "activity.file_id.time_created": to_bson(&file_id.time_created)?,
+            });
+        }
+    }
+    if matches.is_empty() {
+        return Ok(None);
+    }
+    let filter = doc! { "user_id": user_id, "$or": matches };
+    let options = FindOneOptions::builder()
+        .projection(doc! { "_id": 1 })
+        .build();
+    Ok(app_state
+        .db
+        .collection
+        .find_one(filter, options)
+        .await?
+        .and_then(|document| document.get_object_id("_id").ok()))
+}
+
+/// Stores an analysed activity with its record streams and the personal
+/// records it sets against the activities before it.
+async fn store_activity(
+    app_state: &AppState,
+    user_id: &str,
+    mongo_doc: &MongoSchema,
+) -> Result<(ObjectId, Vec<PersonalRecord>), AppError> {
+    let mut new_records = match mongo_doc.summary.start_time {
+        Some(start_time) => {
+            let filter = doc! {
+                "user_id": user_id,
+                "summary.start_time": { "$lte": to_bson(&start_time)? },
+            };
+            let history: Vec<_> = load_record_curves(app_state, filter)
+                .await?
+                .into_iter()
+                .filter_map(|c| Some((c.summary.start_time?, c.power_curve)))
+                .collect();
+            detect_personal_records(
+                user_id,
+                start_time,
+                &mongo_doc.power_curve,
+                mongo_doc.metrics.weight,
+                &history,
+            )
+        }
+        None => Vec::new(),
+    };
+    let document = to_document(mongo_doc)?;
+    let result = app_state.db.collection.insert_one(document, None).await?;
+    let id = result
+        .inserted_id
+        .as_object_id()
+        .ok_or_else(|| AppError::Serialization("Inserted id is not an ObjectId".to_owned()))?;
+    for record in new_records.iter_mut() {
+        record.activity_id = id.to_hex();
+    }
+    if let Err(error) = store_activity_data(app_state, user_id, id, mongo_doc, &new_records).await {
+        // a half-stored activity would hide the failed file as a duplicate
+        remove_activity(app_state, id).await;
+        return Err(error);
+    }
+    Ok((id, new_records))
+}
+
+/// Stores the record streams and personal records of the activity stored as `id`.
+async fn store_activity_data(
+    app_state: &AppState,
+    user_id: &str,
+    id: ObjectId,
+    mongo_doc: &MongoSchema,
+    new_records: &[PersonalRecord],
+) -> Result<(), AppError> {
+    let mut streams = RecordStreams::from_records(user_id, &mongo_doc.activity.records);
+    streams.activity_id = id.to_hex();
+    let documents = streams
+        .chunks()
+        .iter()
+        .map(to_document)
+        .collect::<Result<Vec<_>, _>>()?;
+    app_state.db.streams.insert_many(documents, None).await?;
+    if !new_records.is_empty() {
+        let documents = new_records
+            .iter()
+            .map(to_document)
+            .collect::<Result<Vec<_>, _>>()?;
+        app_state
+            .db
+            .personal_records
+            .insert_many(documents, None)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Deletes the activity stored as `id` with whatever of its streams and
+/// personal records were stored.
+async fn remove_activity(app_state: &AppState, id: ObjectId) {
+    let activity_id = id.to_hex();
+    let results = [
+        app_state
+            .db
+            .personal_records
+            .delete_many(doc! { "activity_id": &activity_id }, None)
+            .await
+            .map(|_| ()),
+        app_state
+            .db
+            .streams
+            .delete_many(doc! { "activity_id": &activity_id }, None)
+            .await
+            .map(|_| ()),
+        app_state
+            .db
+            .collection
+            .delete_one(doc! { "_id": id }, None)
+            .await
+            .map(|_| ()),
+    ];
+    for error in results.into_iter().filter_map(Result::err) {
+        eprintln!("Error removing activity {activity_id}: {error:?}");
+    }
+}
+
+/// Accepts any number of FIT files, and ZIP archives of them, per request.
+/// Files are analysed concurrently and stored oldest first, so personal
+/// records and FTP updates are judged against the activities before them.
+/// A file that fails only fails its own entry in `files`. Files matching a
+/// stored activity are skipped unless `force` is set. The response is 201
+/// when at least one activity was stored, 409 when the rest were duplicates
+/// and 422 otherwise.
+pub async fn process_file(
+    Path(user_id): Path<String>,
+    Query(options): Query<AnalysisOptions>,
+    Query(upload_options): Query<UploadOptions>,
+    State(app_state): State<Arc<AppState>>,
+    mut multipart: Multipart,
+) -> Result<(StatusCode, Json<UploadResponse>), AppError> {
+    options.validate()?;
+    let profile = load_profile(&app_state, &user_id).await?;
+    let mut received = Vec::new();
+    while let Some(field) = multipart.next_field().await? {
+        let name = match (field.file_name(), field.name()) {
+            (Some(file_name), _) => file_name.to_owned(),
+            (None, Some(name @ ("file" | "files"))) => name.to_owned(),
+            _ => continue,
+        };
+        let bytes = field.bytes().await?;
+        received.push(UploadedFile { name, bytes });
+    }
+    if received.is_empty() {
+        return Err(AppError::MissingFile);
+    }
+    let uploads = tokio::task::spawn_blocking(move || {
+        let mut budget = UnpackBudget::default();
+        received
+            .into_iter()
+            .flat_map(|file| expand_archive(file, &mut budget))
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("Unpacking the upload failed: {e}")))?;
+
+    let mut names = Vec::with_capacity(uploads.len());
+    let mut statuses: Vec<Option<FileStatus>> = Vec::with_capacity(uploads.len());
+    let mut readable = Vec::new();
+    let mut positions = Vec::new();
+    for (index, upload) in uploads.into_iter().enumerate() {
+        match upload {
+            Ok(file) => {
+                names.push(file.name.clone());
+                statuses.push(None);
+                positions.push(index);
+                readable.push(file);
+            }
+            Err((name, error)) => {
+                names.push(name);
+                statuses.push(Some(FileStatus::failed(error)));
+            }
+        }
+    }
+
+    let analysed = analyse_files(readable, &user_id, &options, profile.as_ref()).await;
+    let mut activities_by_start = Vec::new();
+    for (index, result) in positions.into_iter().zip(analysed) {
+        match result {
+            Ok(mongo_doc) => activities_by_start.push((index, mongo_doc)),
+            Err(error) => statuses[index] = Some(FileStatus::failed(error)),
+        }
+    }
+    activities_by_start.sort_by_key(|(_, mongo_doc)| mongo_doc.summary.start_time);
+
+    let _user_lock = app_state.upload_locks.lock(&user_id).await;
+    // the profile may have changed while the files were analysed
+    let mut profile = load_profile(&app_state, &user_id).await?;
+
+    let mut activity_ids = Vec::new();
+    let mut activities = Vec::new();
+    let mut personal_records = Vec::new();
+    let mut updated_ftp = None;
+    for (index, mut mongo_doc) in activities_by_start {
+        if upload_options.force {
+            // a deliberate copy stays out of the unique duplicate indexes,
+            // the original still matches later uploads
+            mongo_doc.content_hash = None;
+            mongo_doc.file_fingerprint = None;
+        } else {
+            match find_duplicate(&app_state, &user_id, &mongo_doc).await {
+                Ok(Some(existing_id)) => {
+                    statuses[index] = Some(FileStatus::Duplicate {
+                        existing_id: existing_id.to_hex(),
+                    });
+                    continue;
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    statuses[index] = Some(FileStatus::failed(error));
+                    continue;
+                }
+            }
+        }
+        let (id, mut new_records) = match store_activity(&app_state, &user_id, &mongo_doc).await {
+            Ok(stored) => stored,
+            Err(AppError::Database(error)) if is_duplicate_key(&error) => {
+                // a concurrent upload stored the same file first
+                statuses[index] = Some(
+                    match find_duplicate(&app_state, &user_id, &mongo_doc).await {
+                        Ok(Some(existing_id)) => FileStatus::Duplicate {
+                            existing_id: existing_id.to_hex(),
+                        },
+                        _ => FileStatus::failed(AppError::Database(error)),
+                    },
+                );
+                continue;
+            }
+            Err(error) => {
+                statuses[index] = Some(FileStatus::failed(error));
+                continue;
+            }
+        };
+        if let Some(profile) = profile.as_mut() {
+            let start_time = mongo_doc.summary.start_time;
+            // the activity is stored either way, so a failed update is only logged
+            match update_profile_ftp(&app_state, profile, start_time).await {
+                Ok(Some(estimate)) => updated_ftp = Some(estimate),
+                Ok(None) => {}
+                Err(error) => eprintln!("Error updating FTP {error:?}"),
+            }
+        }
+        statuses[index] = Some(FileStatus::Created { id: id.to_hex() });
+        activity_ids.push(id.to_hex());
+        activities.push(UploadedActivity {
+            id: id.to_hex(),
+            record_count: mongo_doc.activity.records.len(),
+            summary: mongo_doc.summary,
+            metrics: mongo_doc.metrics,
+            power_curve: mongo_doc.power_curve,
+        });
+        personal_records.append(&mut new_records);
+    }
+
+    let files: Vec<FileResult> = names
+        .into_iter()
+        .zip(statuses)
+        .filter_map(|(file_name, status)| {
+            Some(FileResult {
+                file_name,
+                status: status?,
+            })
+        })
+        .collect();
+    let duplicates = files
+        .iter()
+        .any(|file| matches!(file.status, FileStatus::Duplicate { .. }));
+    let status = match (activity_ids.is_empty(), duplicates) {
+        (false, _) => StatusCode::CREATED,
+        (true, true) => StatusCode::CONFLICT,
+        (true, false) => StatusCode::UNPROCESSABLE_ENTITY,
+    };
+    Ok((
+        status,
+        Json(UploadResponse {
+            message: format!("Processed {} of {} files", activity_ids.len(), files.len()),
+            activity_ids,
+            activities,
+            personal_records,
+            updated_ftp,
+            files,
+        }),
+    ))
+}
+
+/// A stored activity of `user_id`, 404 when it doesn't exist or belongs to
+/// someone else, so ids of other users' activities aren't confirmed.
+async fn find_user_activity(
+    app_state: &AppState,
+    user_id: &str,
+    activity_id: &str,
+    projection: Option<Document>,
+) -> Result<Document, AppError> {
+    // a malformed id can never match a stored activity
+    let id = ObjectId::parse_str(activity_id).map_err(|_| AppError::not_found("Activity"))?;
+    let options = FindOneOptions::builder().projection(projection).build();
+    let document = app_state
+        .db
+        .collection
+        .find_one(doc! { "_id": id, "user_id": user_id }, options)
+        .await?
+        .ok_or_else(|| AppError::not_found("Activity"))?;
+    Ok(document)
+}
+
+pub async fn get_activity(
+    Path((user_id, activity_id)): Path<(String, String)>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let document = find_user_activity(&app_state, &user_id, &activity_id, None).await?;
+    Ok(Json(Bson::Document(document).into_relaxed_extjson()))
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredLaps {
+    #[serde(default)]
+    laps: Vec<LapStats>,
+}
+
+pub async fn get_laps(
+    Path((user_id, activity_id)): Path<(String, String)>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<Vec<LapStats>>, AppError> {
+    let document =
+        find_user_activity(&app_state, &user_id, &activity_id, Some(doc! { "laps": 1 })).await?;
+    let stored: StoredLaps = from_document(document)?;
+    Ok(Json(stored.laps))
+}
+
+pub async fn get_streams(
+    Path((user_id, activity_id)): Path<(String, String)>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<RecordStreams>, AppError> {
+    find_user_activity(&app_state, &user_id, &activity_id, Some(doc! { "_id": 1 })).await?;
+    let options = FindOptions::builder().projection(doc! { "_id": 0 }).build();
+    let mut cursor = app_state
+        .db
+        .streams
+        .find(doc! { "activity_id": &activity_id }, options)
+        .await?;
+    let mut chunks = Vec::new();
+    while cursor.advance().await? {
+        chunks.push(from_document(cursor.deserialize_current()?)?);
+    }
+    let streams = RecordStreams::merge(chunks).ok_or_else(|| AppError::not_found("Streams"))?;
+    Ok(Json(streams))
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredCompliance {
+    compliance: Option<ComplianceReport>,
+}
+
+/// 404 as well when the activity has no planned workout to compare against.
+pub async fn get_compliance(
+    Path((user_id, activity_id)): Path<(String, String)>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<ComplianceReport>, AppError> {
+    let projection = doc! { "compliance": 1 };
+    let document = find_user_activity(&app_state, &user_id, &activity_id, Some(projection)).await?;
+    let stored: StoredCompliance = from_document(document)?;
+    stored
+        .compliance
+        .map(Json)
+        .ok_or_else(|| AppError::not_found("Planned workout"))
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredIntervals {
+    #[serde(default)]
+    intervals: Vec<Split>,
+}
+
+pub async fn get_intervals(
+    Path((user_id, activity_id)): Path<(String, String)>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<Vec<Split>>, AppError> {
+    let projection = doc! { "intervals": 1 };
+    let document = find_user_activity(&app_state, &user_id, &activity_id, Some(projection)).await?;
+    let stored: StoredIntervals = from_document(document)?;
+    Ok(Json(stored.intervals))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListActivitiesQuery {
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredSummary {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    #[serde(default)]
+    summary: ActivitySummary,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActivityListItem {
+    id: String,
+    #[serde(flatten)]
+    summary: ActivitySummary,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActivityList {
+    activities: Vec<ActivityListItem>,
+    next_cursor: Option<String>,
+}
+
+/// Builds the filter for the page after `cursor_id`, following the
+/// `summary.start_time desc, _id desc` ordering used by the listing.
+async fn page_after(
+    app_state: &AppState,
+    user_id: &str,
+    cursor_id: ObjectId,
+) -> Result<Document, AppError> {
+    let options = FindOneOptions::builder()
+        .projection(doc! { "summary.start_time": 1 })
+        .build();
+    let last = app_state
+        .db
+        .collection
+        .find_one(doc! { "_id": cursor_id, "user_id": user_id }, options)
+        .await?
+        .ok_or_else(|| AppError::Validation("Unknown cursor".to_owned()))?;
+    let start_time = last
+        .get_document("summary")
+        .ok()
+        .and_then(|s| s.get("start_time"))
+        .cloned()
+        .unwrap_or(Bson::Null);
+
+    let mut branches =
+        vec![doc! { "summary.start_time": start_time.clone(), "_id": { "$lt": cursor_id } }];
+    if start_time != Bson::Null {
+        // activities without a start time sort after every dated one
+        branches.push(doc! { "summary.start_time": { "$lt": start_time } });
+        branches.push(doc! { "summary.start_time": Bson::Null });
+    }
+    Ok(doc! { "user_id": user_id, "$or": branches })
+}
+
+pub async fn list_activities(
+    Path(user_id): Path<String>,
+    Query(query): Query<ListActivitiesQuery>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<ActivityList>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let filter = match &query.cursor {
+        Some(cursor) => {
+            let cursor_id = ObjectId::parse_str(cursor)
+                .map_err(|_| AppError::Validation("Malformed cursor".to_owned()))?;
+            page_after(&app_state, &user_id, cursor_id).await?
+        }
+        None => doc! { "user_id": &user_id },
+    };
+    let options = FindOptions::builder()
+        .projection(doc! { "summary": 1 })
+        .sort(doc! { "summary.start_time": -1, "_id": -1 })
+        .limit(limit + 1)
+        .build();
+
+    let mut cursor = app_state.db.collection.find(filter, options).await?;
+    let mut activities = Vec::new();
+    while cursor.advance().await? {
+        let stored: StoredSummary = from_document(cursor.deserialize_current()?)?;
+        activities.push(ActivityListItem {
+            id: stored.id.to_hex(),
+            summary: stored.summary,
+        });
+    }
+
+    let next_cursor = if activities.len() as i64 > limit {
+        activities.truncate(limit as usize);
+        activities.last().map(|a| a.id.clone())
+    } else {
+        None
+    };
+    Ok(Json(ActivityList {
+        activities,
+        next_cursor,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PowerCurveQuery {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    sport: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BestPower {
+    duration: usize,
+    power: f32,
+    activity_id: String,
+    start_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BestPowerCurve {
+    power_curve: Vec<BestPower>,
+    /// Best W/kg per duration, which may come from other activities than the
+    /// best absolute power. Activities without a known weight are skipped.
+    power_curve_wkg: Vec<BestPower>,
+}
+
+type ActivityRef = (ObjectId, Option<DateTime<Utc>>);
+
+fn best_powers(curves: &[(ActivityRef, MeanMaxCurve)]) -> Vec<BestPower> {
+    merge_mean_max_curves(curves)
+        .into_iter()
+        .map(|(duration, power, (id, start_time))| BestPower {
+            duration,
+            power,
+            activity_id: id.to_hex(),
+            start_time,
+        })
+        .collect()
+}
+
+pub async fn get_power_curve(
+    Path(user_id): Path<String>,
+    Query(query): Query<PowerCurveQuery>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<BestPowerCurve>, AppError> {
+    let filter = activities_filter(&user_id, query.from, query.to, query.sport.as_deref())?;
+    let stored = load_power_curves(&app_state, filter).await?;
+    let power_curve_wkg = best_powers(
+        &stored
+            .iter()
+            .filter_map(|c| Some(((c.id, c.summary.start_time), c.power_curve_wkg.clone()?)))
+            .collect::<Vec<_>>(),
+    );
+    let power_curve = best_powers(
+        &stored
+            .into_iter()
+            .map(|c| ((c.id, c.summary.start_time), c.power_curve))
+            .collect::<Vec<_>>(),
+    );
+    Ok(Json(BestPowerCurve {
+        power_curve,
+        power_curve_wkg,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PersonalRecordsQuery {
+    activity_id: Option<String>,
+    period: Option<RecordPeriod>,
+}
+
+pub async fn list_personal_records(
+    Path(user_id): Path<String>,
+    Query(query): Query<PersonalRecordsQuery>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<Vec<PersonalRecord>>, AppError> {
+    let mut filter = doc! { "user_id": &user_id };
+    if let Some(activity_id) = &query.activity_id {
+        filter.insert("activity_id", activity_id);
+    }
+    if let Some(period) = query.period {
+        filter.insert("period", to_bson(&period)?);
+    }
+    let options = FindOptions::builder()
+        .sort(doc! { "start_time": -1, "duration": 1 })
+        .build();
+
+    let mut cursor = app_state.db.personal_records.find(filter, options).await?;
+    let mut records = Vec::new();
+    while cursor.advance().await? {
+        records.push(from_document(cursor.deserialize_current()?)?);
+    }
+    Ok(Json(records))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PmcQuery {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredLoad {
+    #[serde(default)]
+    summary: ActivitySummary,
+    #[serde(default)]
+    metrics: ActivityMetrics,
+}
+
+pub async fn get_pmc(
+    Path(user_id): Path<String>,
+    Query(query): Query<PmcQuery>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<Vec<PmcDay>>, AppError> {
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = match query.from {
+        Some(from) => from,
+        None => shift_days(to, -90)?,
+    };
+    if from > to {
+        return Err(AppError::Validation("`from` is after `to`".to_owned()));
+    }
+    if (to - from).num_days() >= MAX_PMC_DAYS {
+        return Err(AppError::Validation(format!(
+            "The chart spans at most {MAX_PMC_DAYS} days"
+        )));
+    }
+    // load before `from` warms up CTL and ATL
+    let since = shift_days(from, -MAX_WARMUP_DAYS)?;
+    let filter = activities_filter(&user_id, Some(since), Some(to), None)?;
+    let options = FindOptions::builder()
+        .projection(doc! { "summary.start_time": 1, "metrics.training_load": 1 })
+        .build();
+
+    let mut cursor = app_state.db.collection.find(filter, options).await?;
+    let mut daily_load = BTreeMap::new();
+    while cursor.advance().await? {
+        let stored: StoredLoad = from_document(cursor.deserialize_current()?)?;
+        if let (Some(start_time), Some(load)) =
+            (stored.summary.start_time, stored.metrics.training_load)
+        {
+            *daily_load.entry(start_time.date_naive()).or_insert(0.0) += load;
+        }
+    }
+    Ok(Json(calculate_pmc(&daily_load, from, to)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CriticalPowerQuery {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    sport: Option<String>,
+    model: Option<CriticalPowerModel>,
+    activity_id: Option<String>,
+}
+
+/// Best power curve of the user's activities matching the query, or of a
+/// single activity when `activity_id` is given.
+async fn best_power_curve(
+    app_state: &AppState,
+    user_id: &str,
+    query: &CriticalPowerQuery,
+) -> Result<MeanMaxCurve, AppError> {
+    let filter = match &query.activity_id {
+        Some(activity_id) => {
+            let id =
+                ObjectId::parse_str(activity_id).map_err(|_| AppError::not_found("Activity"))?;
+            doc! { "_id": id, "user_id": user_id }
+        }
+        None => activities_filter(user_id, query.from, query.to, query.sport.as_deref())?,
+    };
+    let stored = load_power_curves(app_state, filter).await?;
+    if query.activity_id.is_some() && stored.is_empty() {
+        return Err(AppError::not_found("Activity"));
+    }
+    Ok(merge_power_curves(stored))
+}
+
+/// Best power per duration over all `stored` curves.
+fn merge_power_curves(stored: Vec<StoredPowerCurve>) -> MeanMaxCurve {
+    let curves: Vec<_> = stored.into_iter().map(|c| ((), c.power_curve)).collect();
+    merge_mean_max_curves(&curves)
+        .into_iter()
+        .map(|(duration, power, _)| (duration, power))
+        .collect()
+}
+
+pub async fn get_critical_power(
+    Path(user_id): Path<String>,
+    Query(query): Query<CriticalPowerQuery>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<CriticalPowerFit>, AppError> {
+    let curve = best_power_curve(&app_state, &user_id, &query).await?;
+    fit_critical_power(&curve, query.model.unwrap_or_default())
+        .map(Json)
+        .ok_or_else(|| {
+            AppError::InsufficientData("Not enough of the 2-20 min power curve to fit".to_owned())
+        })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FtpEstimateQuery {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    sport: Option<String>,
+    method: Option<FtpMethod>,
+}
+
+pub async fn get_ftp_estimate(
+    Path(user_id): Path<String>,
+    Query(query): Query<FtpEstimateQuery>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<FtpEstimate>, AppError> {
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = match query.from {
+        Some(from) => from,
+        None => shift_days(to, -DEFAULT_ESTIMATE_DAYS)?,
+    };
+    let filter = activities_filter(&user_id, Some(from), Some(to), query.sport.as_deref())?;
+    let curve = merge_power_curves(load_power_curves(&app_state, filter).await?);
+    estimate_ftp(&curve, query.method.unwrap_or_default())
+        .map(Json)
+        .ok_or_else(|| AppError::InsufficientData("No efforts to estimate FTP from".to_owned()))
+}
+
+/// Raises the profile's FTP from the activity's start on when the power
+/// curve of the preceding weeks estimates a higher one than is set, if the
+/// profile opted in to automatic updates.
+async fn update_profile_ftp(
+    app_state: &AppState,
+    profile: &mut UserProfile,
+    start_time: Option<DateTime<Utc>>,
+) -> Result<Option<FtpEstimate>, AppError> {
+    let (Some(method), Some(start_time)) = (profile.auto_update_ftp, start_time) else {
+        return Ok(None);
+    };
+    let since = start_time - Duration::days(DEFAULT_ESTIMATE_DAYS);
+    let filter = doc! {
+        "user_id": &profile.user_id,
+        "summary.start_time": {
+            "$gte": to_bson(&since)?,
+            "$lte": to_bson(&start_time)?,
+        },
+    };
+    let curve = merge_power_curves(load_power_curves(app_state, filter).await?);
+    let Some(estimate) = estimate_ftp(&curve, method) else {
+        return Ok(None);
+    };
+    let current = profile.settings_at(start_time).cloned().unwrap_or_default();
+    if current.ftp.is_some_and(|ftp| ftp >= estimate.ftp) {
+        return Ok(None);
+    }
+    let settings = AthleteSettings {
+        effective_from: start_time,
+        ftp: Some(estimate.ftp),
+        ..current
+    };
+    *profile = save_settings(app_state, &profile.user_id, &settings).await?;
+    Ok(Some(estimate))
+}
+
+async fn load_profile(
+    app_state: &AppState,
+    user_id: &str,
+) -> Result<Option<UserProfile>, AppError> {
+    app_state
+        .db
+        .users
+        .find_one(doc! { "user_id": user_id }, None)
+        .await?
+        .map(from_document)
+        .transpose()
+        .map_err(AppError::from)
+}
+
+/// Adds `settings` to the stored profile of `user_id`, replacing an entry
+/// with the same `effective_from`. Only that entry is written, so changes
+/// made to the rest of the profile in the meantime are kept.
+async fn save_settings(
+    app_state: &AppState,
+    user_id: &str,
+    settings: &AthleteSettings,
+) -> Result<UserProfile, AppError> {
+    let users = &app_state.db.users;
+    let pull = doc! {
+        "$pull": { "settings": { "effective_from": to_bson(&settings.effective_from)? } },
+    };
+    users
+        .update_one(doc! { "user_id": user_id }, pull, None)
+        .await?;
+    let push = doc! {
+        "$push": {
+            "settings": {
+                "$each": [to_bson(settings)?],
+                "$sort": { "effective_from": 1 },
+            },
+        },
+    };
+    let options = FindOneAndUpdateOptions::builder()
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .build();
+    let document = users
+        .find_one_and_update(doc! { "user_id": user_id }, push, options)
+        .await?
+        .ok_or_else(|| AppError::not_found("Profile"))?;
+    Ok(from_document(document)?)
+}
+
+async fn save_profile(app_state: &AppState, profile: &UserProfile) -> Result<(), AppError> {
+    let document = to_document(profile)?;
+    let options = ReplaceOptions::builder().upsert(true).build();
+    app_state
+        .db
+        .users
+        .replace_one(doc! { "user_id": &profile.user_id }, document, options)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_profile(
+    Path(user_id): Path<String>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<UserProfile>, AppError> {
+    load_profile(&app_state, &user_id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| AppError::not_found("Profile"))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProfileBody {
+    settings: Vec<AthleteSettings>,
+    #[serde(default)]
+    auto_update_ftp: Option<FtpMethod>,
+}
+
+pub async fn put_profile(
+    Path(user_id): Path<String>,
+    State(app_state): State<Arc<AppState>>,
+    Json(body): Json<ProfileBody>,
+) -> Result<Json<UserProfile>, AppError> {
+    for settings in &body.settings {
+        settings.validate()?;
+    }
+    let mut profile = UserProfile {
+        user_id,
+        settings: body.settings,
+        auto_update_ftp: body.auto_update_ftp,
+    };
+    profile.settings.sort_by_key(|s| s.effective_from);
+    save_profile(&app_state, &profile).await?;
+    Ok(Json(profile))
+}
+
+/// Adds an effective-dated settings entry, replacing one with the same date.
+pub async fn add_profile_settings(
+    Path(user_id): Path<String>,
+    State(app_state): State<Arc<AppState>>,
+    Json(settings): Json<AthleteSettings>,
+) -> Result<Json<UserProfile>, AppError> {
+    settings.validate()?;
+    Ok(Json(save_settings(&app_state, &user_id, &settings).await?))
+}
+
+pub async fn delete_profile(
+    Path(user_id): Path<String>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<StatusCode, AppError> {
+    let result = app_state
+        .db
+        .users
+        .delete_one(doc! { "user_id": &user_id }, None)
+        .await?;
+    if result.deleted_count == 0 {
+        return Err(AppError::not_found("Profile"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_at_the_end_of_the_range_are_rejected() {
+        let filter = activities_filter("u", None, Some(NaiveDate::MAX), None);
+        assert!(matches!(filter, Err(AppError::Validation(_))));
+        assert!(matches!(
+            shift_days(NaiveDate::MIN, -90),
+            Err(AppError::Validation(_))
+        ));
+        let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        assert_eq!(
+            shift_days(day, -1).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use chrono::{DateTime, Utc};
+
+use crate::metrics::normalized_power;
+use crate::resample::{resample_timed, GapHandling, DEFAULT_MAX_GAP};
+use crate::structures::Split;
+
+/// Efforts above this fraction of FTP count as intervals by default.
+pub const DEFAULT_INTERVAL_THRESHOLD: f64 = 1.05;
+pub const DEFAULT_INTERVAL_MIN_DURATION: usize = 30;
+/// Efforts separated by at most this many seconds are merged by default.
+pub const DEFAULT_INTERVAL_MERGE_GAP: usize = 15;
+
+#[derive(Clone, Copy, Debug)]
+pub struct IntervalSettings {
+    /// Fraction of FTP an effort has to stay above.
+    pub threshold: f64,
+    /// Shortest effort in seconds that counts as an interval.
+    pub min_duration: usize,
+    /// Longest dip below the threshold in seconds that doesn't end an effort.
+    pub merge_gap: usize,
+}
+
+impl Default for IntervalSettings {
+    fn default() -> Self {
+        IntervalSettings {
+            threshold: DEFAULT_INTERVAL_THRESHOLD,
+            min_duration: DEFAULT_INTERVAL_MIN_DURATION,
+            merge_gap: DEFAULT_INTERVAL_MERGE_GAP,
+        }
+    }
+}
+
+/// `[start, end)` second offsets of every run of `power` at or above `watts`,
+/// with runs closer than `merge_gap` joined together.
+fn efforts(power: &[f64], watts: f64, merge_gap: usize) -> Vec<(usize, usize)> {
+    let mut efforts: Vec<(usize, usize)> = Vec::new();
+    let mut start = None;
+    for (i, &p) in power.iter().chain([0.0].iter()).enumerate() {
+        match (start, p >= watts) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                match efforts.last_mut() {
+                    Some(last) if s - last.1 <= merge_gap => last.1 = i,
+                    _ => efforts.push((s, i)),
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    efforts
+}
+
+/// The non-zero values of timed 1 Hz `segments` within `[from, to)`.
+fn values_between(segments: &[(i64, Vec<f64>)], from: i64, to: i64) -> Vec<f64> {
+    segments
+        .iter()
+        .flat_map(|(start, values)| {
+            let end = start + values.len() as i64;
+            let (lo, hi) = (from.max(*start), to.min(end));
+            let range = if lo < hi {
+                (lo - start) as usize..(hi - start) as usize
+            } else {
+                0..0
+            };
+            values[range].iter().copied()
+        })
+        .filter(|value| *value > 0.0)
+        .collect()
+}
+
+/// Detects sustained efforts above `settings.threshold` × `ftp` in the
+/// `(unix timestamp, value)` power and heart-rate record samples. Pauses count
+/// as zero power and pauses too long to fill end a segment, so an effort
+/// never spans one.
+pub fn detect_intervals(
+    power: &[(i64, f64)],
+    heart_rate: &[(i64, f64)],
+    ftp: f64,
+    settings: IntervalSettings,
+) -> Vec<Split> {
+    let watts = settings.threshold * ftp;
+    if watts <= 0.0 {
+        return vec![];
+    }
+    let heart_rate = resample_timed(heart_rate, GapHandling::ZeroFill, DEFAULT_MAX_GAP);
+
+    resample_timed(power, GapHandling::ZeroFill, DEFAULT_MAX_GAP)
+        .into_iter()
+        .flat_map(|(segment_start, power)| {
+            efforts(&power, watts, settings.merge_gap)
+                .into_iter()
+                .filter(|(start, end)| end - start >= settings.min_duration)
+                .map(move |(start, end)| {
+                    let (from, to) = (segment_start + start as i64, segment_start + end as i64);
+                    (from, to, power[start..end].to_vec())
+                })
+                .collect::<Vec<_>>()
+        })
+        .filter_map(|(from, to, window)| {
+            let duration = window.len();
+            let total: f64 = window.iter().sum();
+            let heart_rate = values_between(&heart_rate, from, to);
+            let normalized_power = normalized_power(std::slice::from_ref(&window));
+            Some(Split {
+                start_time: DateTime::<Utc>::from_timestamp(from, 0)?,
+                end_time: DateTime::<Utc>::from_timestamp(to, 0)?,
+                name: None,
+                duration,
+                avg_power: total / duration as f64,
+                max_power: window.iter().copied().fold(0.0, f64::max),
+                normalized_power,
+                intensity_factor: normalized_power.map(|np| np / ftp),
+                avg_heart_rate: (!heart_rate.is_empty())
+                    .then(|| heart_rate.iter().sum::<f64>() / heart_rate.len() as f64),
+                work_kj: total / 1000.0,
+            })
+        })
+        .enumerate()
+        .map(|(i, split)| Split {
+            name: Some(format!("Interval {}", i + 1)),
+            ..split
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(watts: &[(usize, f64)]) -> Vec<(i64, f64)> {
+        let series: Vec<f64> = watts
+            .iter()
+            .flat_map(|&(seconds, w)| std::iter::repeat_n(w, seconds))
+            .collect();
+        series
+            .into_iter()
+            .enumerate()
+            .map(|(i, w)| (1_700_000_000 + i as i64, w))
+            .collect()
+    }
+
+    #[test]
+    fn efforts_merge_short_dips() {
+        let power = [0.0, 300.0, 300.0, 0.0, 300.0, 0.0, 0.0, 0.0, 300.0];
+        assert_eq!(efforts(&power, 250.0, 1), vec![(1, 5), (8, 9)]);
+        assert_eq!(efforts(&power, 250.0, 0), vec![(1, 3), (4, 5), (8, 9)]);
+        assert!(efforts(&power, 400.0, 1).is_empty());
+    }
+
+    #[test]
+    fn detects_sustained_efforts() {
+        let power = samples(&[(300, 150.0), (120, 300.0), (300, 150.0), (20, 400.0)]);
+        let heart_rate: Vec<(i64, f64)> = power.iter().map(|&(t, _)| (t, 150.0)).collect();
+        let intervals = detect_intervals(&power, &heart_rate, 250.0, IntervalSettings::default());
+        assert_eq!(intervals.len(), 1);
+        let interval = &intervals[0];
+        assert_eq!(interval.name.as_deref(), Some("Interval 1"));
+        assert_eq!(interval.duration, 120);
+        assert_eq!(interval.avg_power, 300.0);
+        assert_eq!(interval.start_time.timestamp(), 1_700_000_300);
+        assert_eq!(interval.avg_heart_rate, Some(150.0));
+        assert!((interval.work_kj - 36.0).abs() < 1e-9);
+        assert!((interval.intensity_factor.unwrap() - 1.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn efforts_never_span_a_long_pause() {
+        let mut power = samples(&[(60, 400.0)]);
+        let resumed = power.last().unwrap().0 + 83 * 60;
+        power.extend((0..60).map(|i| (resumed + i, 400.0)));
+        let heart_rate: Vec<(i64, f64)> = power.iter().map(|&(t, _)| (t, 160.0)).collect();
+        let settings = IntervalSettings {
+            min_duration: 60,
+            ..Default::default()
+        };
+        let intervals = detect_intervals(&power, &heart_rate, 250.0, settings);
+        assert_eq!(intervals.len(), 2);
+        assert_eq!(intervals[0].duration, 60);
+        assert_eq!(intervals[0].end_time.timestamp(), 1_700_000_060);
+        assert_eq!(intervals[1].name.as_deref(), Some("Interval 2"));
+        assert_eq!(intervals[1].start_time.timestamp(), resumed);
+        assert_eq!(intervals[1].end_time.timestamp(), resumed + 60);
+        assert_eq!(intervals[1].avg_heart_rate, Some(160.0));
+    }
+
+    #[test]
+    fn needs_power_and_ftp() {
+        let power = samples(&[(120, 300.0)]);
+        assert!(detect_intervals(&[], &[], 250.0, IntervalSettings::default()).is_empty());
+        assert!(detect_intervals(&power, &[], 0.0, IntervalSettings::default()).is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::normalized_power;
+use crate::resample::{resample, GapHandling};
+use crate::structures::Lap;
+
+/// Stats of a single lap, as recorded by the device and recomputed from the
+/// power samples within the lap.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LapStats {
+    pub index: i64,
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub intensity: Option<String>,
+    pub wkt_step_index: Option<i64>,
+    pub timer_time: Option<f64>,   // seconds
+    pub elapsed_time: Option<f64>, // seconds
+    pub distance: Option<f64>,     // meters
+    pub avg_power: Option<f64>,
+    pub max_power: Option<f64>,
+    pub avg_heart_rate: Option<f64>,
+    pub max_heart_rate: Option<f64>,
+    pub avg_cadence: Option<f64>,
+    pub avg_speed: Option<f64>, // m/s
+    pub calories: Option<f64>,
+    pub normalized_power: Option<f64>,
+    pub intensity_factor: Option<f64>,
+    /// Average of the non-zero power samples, i.e. ignoring coasting.
+    pub avg_power_excluding_zeros: Option<f64>,
+}
+
+/// Devices leave fields they didn't record out, which parses as zero.
+fn recorded(value: f64) -> Option<f64> {
+    (value > 0.0).then_some(value)
+}
+
+impl LapStats {
+    /// `power` holds the activity's `(unix timestamp, watts)` record samples.
+    /// A lap covers `[start_time, timestamp)`, so a sample on the boundary
+    /// only counts for the lap it starts.
+    pub fn calculate(
+        lap: &Lap,
+        power: &[(i64, f64)],
+        ftp: Option<f64>,
+        gap_handling: GapHandling,
+        max_gap: i64,
+    ) -> Self {
+        let start = lap.start_time.timestamp();
+        let end = lap.timestamp.timestamp();
+        let samples: Vec<(i64, f64)> = power
+            .iter()
+            .copied()
+            .filter(|&(timestamp, _)| start <= timestamp && timestamp < end)
+            .collect();
+        let normalized_power = if samples.iter().any(|&(_, watts)| watts > 0.0) {
+            normalized_power(&resample(&samples, gap_handling, max_gap))
+        } else {
+            None
+        };
+        let pedalling: Vec<f64> = samples
+            .iter()
+            .map(|&(_, watts)| watts)
+            .filter(|watts| *watts > 0.0)
+            .collect();
+
+        LapStats {
+            index: lap.message_index,
+            start_time: (start > 0).then_some(lap.start_time),
+            end_time: (end > 0).then_some(lap.timestamp),
+            intensity: Some(lap.intensity.clone()).filter(|i| !i.is_empty()),
+            wkt_step_index: lap.wkt_step_index,
+            timer_time: recorded(lap.total_timer_time.value),
+            elapsed_time: recorded(lap.total_elapsed_time.value),
+            distance: recorded(lap.total_distance.value),
+            avg_power: recorded(lap.avg_power.value as f64),
+            max_power: recorded(lap.max_power.value as f64),
+            avg_heart_rate: recorded(lap.avg_heart_rate.value as f64),
+            max_heart_rate: recorded(lap.max_heart_rate.value as f64),
+            avg_cadence: recorded(lap.avg_cadence.value),
+            avg_speed: recorded(lap.enhanced_avg_speed.value),
+            calories: recorded(lap.total_calories.value as f64),
+            normalized_power,
+            intensity_factor: normalized_power.zip(ftp).map(|(np, ftp)| np / ftp),
+            avg_power_excluding_zeros: (!pedalling.is_empty())
+                .then(|| pedalling.iter().sum::<f64>() / pedalling.len() as f64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fit_record, fit_time, steady};
+    use fitparser::{profile::MesgNum, Value};
+
+    fn lap(start: i64, end: i64) -> Lap {
+        Lap::from_fitentry(&fit_record(
+            MesgNum::Lap,
+            vec![
+                ("start_time", fit_time(start)),
+                ("timestamp", fit_time(end)),
+                ("message_index", Value::UInt16(2)),
+                ("total_timer_time", Value::Float64((end - start) as f64)),
+                ("avg_power", Value::UInt16(0)),
+            ],
+        ))
+    }
+
+    #[test]
+    fn recomputes_power_within_the_lap() {
+        let mut power = steady(1_000, 60, 200.0);
+        power.extend(steady(1_060, 60, 0.0));
+        power.extend(steady(1_120, 60, 300.0));
+        let stats = LapStats::calculate(
+            &lap(1_000, 1_120),
+            &power,
+            Some(250.0),
+            GapHandling::ZeroFill,
+            30,
+        );
+        assert_eq!(stats.index, 2);
+        assert_eq!(stats.timer_time, Some(120.0));
+        // devices that don't record a field leave it at zero
+        assert_eq!(stats.avg_power, None);
+        assert_eq!(stats.avg_power_excluding_zeros, Some(200.0));
+        let np = stats.normalized_power.unwrap();
+        assert!(np > 100.0 && np < 200.0);
+        assert_eq!(stats.intensity_factor, Some(np / 250.0));
+    }
+
+    #[test]
+    fn boundary_samples_belong_to_the_next_lap() {
+        let power = vec![(1_000, 100.0), (1_060, 400.0)];
+        let first =
+            LapStats::calculate(&lap(1_000, 1_060), &power, None, GapHandling::ZeroFill, 30);
+        let second =
+            LapStats::calculate(&lap(1_060, 1_120), &power, None, GapHandling::ZeroFill, 30);
+        assert_eq!(first.avg_power_excluding_zeros, Some(100.0));
+        assert_eq!(second.avg_power_excluding_zeros, Some(400.0));
+    }
+
+    #[test]
+    fn laps_without_power_have_no_power_stats() {
+        let stats = LapStats::calculate(
+            &lap(1_000, 1_060),
+            &[],
+            Some(250.0),
+            GapHandling::ZeroFill,
+            30,
+        );
+        assert_eq!(stats.normalized_power, None);
+        assert_eq!(stats.intensity_factor, None);
+        assert_eq!(stats.avg_power_excluding_zeros, None);
+    }
+}
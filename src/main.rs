@@ -1,111 +1,53 @@
+mod analysis;
+mod channels;
+mod compliance;
+mod critical_power;
 mod db;
+mod error;
+mod extract;
+mod ftp;
+mod handlers;
+mod intervals;
+mod laps;
+mod metrics;
+mod pmc;
 mod power_curve;
+mod records;
+mod resample;
+mod streams;
 mod structures;
+mod summary;
+#[cfg(test)]
+mod test_support;
+mod upload;
+mod users;
+mod wbal;
+mod zones;
 
-use bson::to_document;
 use db::DB;
-use fitparser::{from_reader, profile::MesgNum};
-use power_curve::calculate_power_curve;
-use std::{collections::BTreeMap, sync::Arc};
-use structures::*;
+use handlers::{
+    add_profile_settings, delete_profile, get_activity, get_compliance, get_critical_power,
+    get_ftp_estimate, get_intervals, get_laps, get_pmc, get_power_curve, get_profile, get_streams,
+    list_activities, list_personal_records, process_file, put_profile,
+};
+use std::sync::Arc;
 use tower_http::cors::CorsLayer;
+use upload::UserLocks;
 
 use axum::{
-    extract::{Multipart, Path, State},
+    extract::DefaultBodyLimit,
     http::{
         header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
-        HeaderValue, Method, StatusCode,
+        HeaderValue, Method,
     },
-    response::{Json, Response},
-    routing::post,
+    routing::{get, post},
     Router,
 };
-use axum::{handler::Handler, response::IntoResponse};
-
-#[derive(Debug, serde::Serialize)]
-struct UploadResponse {
-    message: String,
-}
-
-async fn process_file(
-    Path(user_id): Path<String>,
-    State(app_state): State<Arc<AppState>>,
-    mut multipart: Multipart,
-) -> Result<Response, StatusCode> {
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        if field.name() == Some("file") {
-            let file_bytes = field.bytes().await.unwrap();
-            let data = from_reader(&mut file_bytes.as_ref()).map_err(|e| {
-                println!("Error parsing file {e:?}");
-                StatusCode::BAD_REQUEST
-            })?;
-            println!("Length of fit file {}", data.len());
-            // let mut workout_session = WorkoutSession::default();
-            let data: FitDataMap = data.into_iter().fold(BTreeMap::new(), merge_by_kind);
-            let power_data: Vec<u64> = data
-                .get(&MesgNum::Record)
-                .and_then(|x| {
-                    Some(
-                        x.iter()
-                            .map(|entry| {
-                                let value: i64 = entry
-                                    .get("power")
-                                    .and_then(|v| v.value.to_owned().try_into().ok())
-                                    .unwrap_or_default();
-                                value as u64
-                            })
-                            .collect(),
-                    )
-                })
-                .unwrap_or_default();
-            let mongo_doc = MongoSchema {
-                user_id: user_id.clone(),
-                fit_data: data,
-                power_curve: calculate_power_curve(&power_data),
-            };
-            let document = to_document(&mongo_doc).map_err(|e| {
-                println!("Error converting to document {e:?}");
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
-            app_state
-                .db
-                .collection
-                .insert_one(document, None)
-                .await
-                .map_err(|e| {
-                    println!("Error inserting into db {e:?}");
-                    StatusCode::INTERNAL_SERVER_ERROR
-                })?;
-        }
-    }
-
-    // Return a response
-    // Ok(Json(UploadResponse {
-    //     message: "File processed successfully".to_string(),
-    // }))
-    Ok(Response::default())
-    // .status(StatusCode::CREATED)
-    // .body(boxed("OK".to_string()))
-    // .unwrap());
-}
-
-// #[axum_macros::debug_handler]
-// async fn process_file(
-//     Path(user_id): Path<String>,
-//     State(app_state): State<Arc<AppState>>,
-//     mut multipart: Multipart,
-// ) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-//     Ok((
-//         StatusCode::CREATED,
-//         Json(UploadResponse {
-//             message: "File processed successfully".to_string(),
-//         }),
-//     ))
-// }
 
 #[derive(Clone)]
 pub struct AppState {
     db: DB,
+    upload_locks: UserLocks,
 }
 
 #[tokio::main]
@@ -114,16 +56,68 @@ async fn main() -> Result<(), mongodb::error::Error> {
 
     let cors = CorsLayer::new()
         .allow_origin("http://localhost:8080".parse::<HeaderValue>().unwrap())
-        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ])
         .allow_credentials(true)
         .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
 
     let app = Router::new()
         .route(
             "/analytics-api/:user_id/upload_activity",
-            post(process_file),
+            post(process_file).layer(DefaultBodyLimit::max(upload::MAX_UPLOAD_BYTES)),
+        )
+        .route("/analytics-api/:user_id/activities", get(list_activities))
+        .route("/analytics-api/:user_id/power_curve", get(get_power_curve))
+        .route(
+            "/analytics-api/:user_id/personal_records",
+            get(list_personal_records),
+        )
+        .route("/analytics-api/:user_id/pmc", get(get_pmc))
+        .route(
+            "/analytics-api/:user_id/critical_power",
+            get(get_critical_power),
+        )
+        .route(
+            "/analytics-api/:user_id/ftp_estimate",
+            get(get_ftp_estimate),
+        )
+        .route(
+            "/analytics-api/:user_id/profile",
+            get(get_profile).put(put_profile).delete(delete_profile),
+        )
+        .route(
+            "/analytics-api/:user_id/profile/settings",
+            post(add_profile_settings),
+        )
+        .route(
+            "/analytics-api/:user_id/activities/:activity_id",
+            get(get_activity),
+        )
+        .route(
+            "/analytics-api/:user_id/activities/:activity_id/laps",
+            get(get_laps),
+        )
+        .route(
+            "/analytics-api/:user_id/activities/:activity_id/intervals",
+            get(get_intervals),
+        )
+        .route(
+            "/analytics-api/:user_id/activities/:activity_id/compliance",
+            get(get_compliance),
+        )
+        .route(
+            "/analytics-api/:user_id/activities/:activity_id/streams",
+            get(get_streams),
         )
-        .with_state(Arc::new(AppState { db: db.clone() }))
+        .with_state(Arc::new(AppState {
+            db: db.clone(),
+            upload_locks: UserLocks::default(),
+        }))
         .layer(cors);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
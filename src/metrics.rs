@@ -0,0 +1,202 @@
+use fitparser::profile::MesgNum;
+use serde::{Deserialize, Serialize};
+
+use crate::structures::FitDataMap;
+
+/// Window of the rolling average used by Normalized Power.
+const NP_WINDOW: usize = 30;
+
+/// Used for TRIMP when neither the user nor the device provides HR limits.
+pub const DEFAULT_RESTING_HR: f64 = 60.0;
+pub const DEFAULT_MAX_HR: f64 = 190.0;
+
+/// Training-load metrics of a single activity.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ActivityMetrics {
+    pub ftp: Option<f64>,
+    pub average_power: Option<f64>,
+    pub normalized_power: Option<f64>,
+    /// Body weight in kilograms the W/kg values below are based on.
+    pub weight: Option<f64>,
+    pub average_power_wkg: Option<f64>,
+    pub normalized_power_wkg: Option<f64>,
+    pub variability_index: Option<f64>,
+    pub intensity_factor: Option<f64>,
+    pub training_stress_score: Option<f64>,
+    pub work_kj: Option<f64>,
+    pub trimp: Option<f64>,
+    /// Stress used for fitness/fatigue modelling: TSS, or TRIMP without power.
+    pub training_load: Option<f64>,
+}
+
+/// FTP the device recorded for this activity, preferring the `zones_target`
+/// message over the session's `threshold_power`.
+pub fn ftp_from_fit_data(data: &FitDataMap) -> Option<f64> {
+    [
+        (MesgNum::ZonesTarget, "functional_threshold_power"),
+        (MesgNum::Session, "threshold_power"),
+    ]
+    .iter()
+    .find_map(|(kind, field)| {
+        data.get(kind)?
+            .first()?
+            .get(*field)?
+            .as_f64()
+            .filter(|ftp| *ftp > 0.0)
+    })
+}
+
+/// Resting and maximum heart rate the device used for its HR zones.
+pub fn hr_limits_from_fit_data(data: &FitDataMap) -> (Option<f64>, Option<f64>) {
+    let time_in_zone = data
+        .get(&MesgNum::TimeInZone)
+        .and_then(|entries| entries.first());
+    let get = |field: &str| {
+        time_in_zone
+            .and_then(|fields| fields.get(field))
+            .and_then(|v| v.as_f64())
+            .filter(|hr| *hr > 0.0)
+    };
+    (get("resting_heart_rate"), get("max_heart_rate"))
+}
+
+/// Banister's TRIMP over 1 Hz heart-rate `segments`, using the exponential
+/// weighting of heart-rate reserve.
+pub fn trimp(segments: &[Vec<f64>], resting_hr: f64, max_hr: f64) -> Option<f64> {
+    if max_hr <= resting_hr || segments.iter().all(|s| s.is_empty()) {
+        return None;
+    }
+    let total = segments
+        .iter()
+        .flatten()
+        .map(|&hr| {
+            let reserve = ((hr - resting_hr) / (max_hr - resting_hr)).clamp(0.0, 1.0);
+            reserve * 0.64 * (1.92 * reserve).exp() / 60.0
+        })
+        .sum();
+    Some(total)
+}
+
+/// Normalized Power: the fourth root of the mean of the fourth powers of the
+/// 30 s rolling average. Rolling windows never span two segments.
+pub fn normalized_power(segments: &[Vec<f64>]) -> Option<f64> {
+    let mut sum = 0.0;
+    let mut count = 0usize;
+    for segment in segments.iter().filter(|s| s.len() >= NP_WINDOW) {
+        let mut window: f64 = segment[..NP_WINDOW].iter().sum();
+        sum += (window / NP_WINDOW as f64).powi(4);
+        count += 1;
+        for i in NP_WINDOW..segment.len() {
+            window += segment[i] - segment[i - NP_WINDOW];
+            sum += (window / NP_WINDOW as f64).powi(4);
+            count += 1;
+        }
+    }
+    (count > 0).then(|| (sum / count as f64).powf(0.25))
+}
+
+impl ActivityMetrics {
+    /// Metrics over the 1 Hz power `segments`; IF and TSS need a positive `ftp`.
+    pub fn calculate(segments: &[Vec<f64>], ftp: Option<f64>) -> Self {
+        let ftp = ftp.filter(|ftp| *ftp > 0.0);
+        let seconds: usize = segments.iter().map(|s| s.len()).sum();
+        if seconds == 0 {
+            return ActivityMetrics {
+                ftp,
+                ..Default::default()
+            };
+        }
+        let total: f64 = segments.iter().flatten().sum();
+        let average_power = total / seconds as f64;
+        let normalized_power = normalized_power(segments).unwrap_or(average_power);
+        let intensity_factor = ftp.map(|ftp| normalized_power / ftp);
+
+        let training_stress_score = ftp.zip(intensity_factor).map(|(ftp, intensity)| {
+            seconds as f64 * normalized_power * intensity / (ftp * 3600.0) * 100.0
+        });
+
+        ActivityMetrics {
+            ftp,
+            average_power: Some(average_power),
+            normalized_power: Some(normalized_power),
+            variability_index: (average_power > 0.0).then(|| normalized_power / average_power),
+            intensity_factor,
+            training_stress_score,
+            work_kj: Some(total / 1000.0),
+            trimp: None,
+            training_load: training_stress_score,
+            ..Default::default()
+        }
+    }
+
+    /// Adds TRIMP from the 1 Hz heart-rate `segments`, which becomes the
+    /// training load when there is no TSS.
+    pub fn with_heart_rate(mut self, segments: &[Vec<f64>], resting_hr: f64, max_hr: f64) -> Self {
+        self.trimp = trimp(segments, resting_hr, max_hr);
+        self.training_load = self.training_stress_score.or(self.trimp);
+        self
+    }
+
+    /// Adds the W/kg variants of average and normalized power.
+    pub fn with_weight(mut self, weight: Option<f64>) -> Self {
+        self.weight = weight.filter(|w| *w > 0.0);
+        if let Some(weight) = self.weight {
+            self.average_power_wkg = self.average_power.map(|p| p / weight);
+            self.normalized_power_wkg = self.normalized_power.map(|p| p / weight);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steady_ride_metrics() {
+        // one hour at a constant 200 W with an FTP of 250 W
+        let segments = vec![vec![200.0; 3600]];
+        let metrics = ActivityMetrics::calculate(&segments, Some(250.0));
+        assert!((metrics.normalized_power.unwrap() - 200.0).abs() < 1e-9);
+        assert!((metrics.variability_index.unwrap() - 1.0).abs() < 1e-9);
+        assert!((metrics.intensity_factor.unwrap() - 0.8).abs() < 1e-9);
+        // 1 h × 0.8² × 100
+        assert!((metrics.training_stress_score.unwrap() - 64.0).abs() < 1e-9);
+        assert!((metrics.work_kj.unwrap() - 720.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn normalized_power_weights_surges() {
+        // alternating 60 s at 300 W and 100 W averages 200 W
+        let segment: Vec<f64> = (0..3600)
+            .map(|i| if (i / 60) % 2 == 0 { 300.0 } else { 100.0 })
+            .collect();
+        let np = normalized_power(&[segment]).unwrap();
+        assert!(np > 200.0 && np < 300.0);
+    }
+
+    #[test]
+    fn normalized_power_needs_a_full_window() {
+        assert_eq!(normalized_power(&[vec![200.0; NP_WINDOW - 1]]), None);
+    }
+
+    #[test]
+    fn trimp_weights_heart_rate_reserve() {
+        // an hour at max HR: 60 min × 0.64 × e^1.92
+        let at_max = trimp(&[vec![190.0; 3600]], 60.0, 190.0).unwrap();
+        assert!((at_max - 60.0 * 0.64 * 1.92f64.exp()).abs() < 1e-6);
+        assert_eq!(trimp(&[vec![60.0; 3600]], 60.0, 190.0), Some(0.0));
+        assert_eq!(trimp(&[vec![150.0; 60]], 190.0, 60.0), None);
+        assert_eq!(trimp(&[], 60.0, 190.0), None);
+    }
+
+    #[test]
+    fn non_positive_ftp_is_ignored() {
+        for ftp in [0.0, -100.0] {
+            let metrics = ActivityMetrics::calculate(&[vec![200.0; 600]], Some(ftp));
+            assert_eq!(metrics.ftp, None);
+            assert_eq!(metrics.intensity_factor, None);
+            assert_eq!(metrics.training_stress_score, None);
+        }
+    }
+}
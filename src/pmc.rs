@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDate};
+use serde::Serialize;
+
+/// Time constants (days) of the chronic and acute training load averages.
+const CTL_DAYS: f64 = 42.0;
+const ATL_DAYS: f64 = 7.0;
+/// Load this many days before `from` still counts; older load has decayed
+/// to nothing.
+pub const MAX_WARMUP_DAYS: i64 = 365;
+/// Longest `[from, to]` span a chart may cover.
+pub const MAX_PMC_DAYS: i64 = 3660;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PmcDay {
+    pub date: NaiveDate,
+    pub training_load: f64,
+    /// Chronic training load, "fitness".
+    pub ctl: f64,
+    /// Acute training load, "fatigue".
+    pub atl: f64,
+    /// Training stress balance, "form".
+    pub tsb: f64,
+}
+
+/// Exponentially weighted fitness/fatigue series for every day in
+/// `[from, to]`. Loads up to `MAX_WARMUP_DAYS` before `from` still warm up
+/// CTL and ATL.
+pub fn calculate_pmc(
+    daily_load: &BTreeMap<NaiveDate, f64>,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Vec<PmcDay> {
+    let ctl_decay = 1.0 - (-1.0 / CTL_DAYS).exp();
+    let atl_decay = 1.0 - (-1.0 / ATL_DAYS).exp();
+    let start = daily_load
+        .keys()
+        .next()
+        .copied()
+        .map_or(from, |first| first.min(from))
+        .max(
+            from.checked_sub_signed(Duration::days(MAX_WARMUP_DAYS))
+                .unwrap_or(NaiveDate::MIN),
+        );
+
+    let mut ctl = 0.0;
+    let mut atl = 0.0;
+    let mut days = Vec::new();
+    let dates = std::iter::successors(Some(start), |date| date.succ_opt());
+    for date in dates.take_while(|date| *date <= to) {
+        let training_load = daily_load.get(&date).copied().unwrap_or_default();
+        ctl += (training_load - ctl) * ctl_decay;
+        atl += (training_load - atl) * atl_decay;
+        if date >= from {
+            days.push(PmcDay {
+                date,
+                training_load,
+                ctl,
+                atl,
+                tsb: ctl - atl,
+            });
+        }
+    }
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(n: i64) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, 1).unwrap() + Duration::days(n)
+    }
+
+    #[test]
+    fn constant_load_converges() {
+        let load: BTreeMap<NaiveDate, f64> = (0..365).map(|n| (day(n), 100.0)).collect();
+        let pmc = calculate_pmc(&load, day(0), day(364));
+        assert_eq!(pmc.len(), 365);
+        let last = pmc.last().unwrap();
+        assert!((last.ctl - 100.0).abs() < 0.1);
+        assert!((last.atl - 100.0).abs() < 1e-6);
+        assert!(last.tsb.abs() < 0.1);
+        // fatigue builds faster than fitness
+        assert!(pmc[6].atl > pmc[6].ctl && pmc[6].tsb < 0.0);
+    }
+
+    #[test]
+    fn earlier_load_warms_up() {
+        let load = BTreeMap::from([(day(0), 100.0)]);
+        let pmc = calculate_pmc(&load, day(10), day(10));
+        assert_eq!(pmc.len(), 1);
+        assert!(pmc[0].ctl > 0.0 && pmc[0].training_load == 0.0);
+    }
+
+    #[test]
+    fn ancient_load_is_ignored() {
+        let load = BTreeMap::from([(NaiveDate::from_ymd_opt(1, 1, 1).unwrap(), 100.0)]);
+        let pmc = calculate_pmc(&load, day(0), day(0));
+        assert_eq!(pmc[0].ctl, 0.0);
+    }
+
+    #[test]
+    fn handles_the_last_supported_date() {
+        let days = calculate_pmc(&BTreeMap::new(), NaiveDate::MAX, NaiveDate::MAX);
+        assert_eq!(days.len(), 1);
+        assert_eq!(days[0].date, NaiveDate::MAX);
+    }
+}
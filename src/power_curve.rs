@@ -1,9 +1,14 @@
+use std::collections::BTreeMap;
+
 use lazy_static::lazy_static;
 use rayon::iter::IntoParallelIterator;
 use rayon::prelude::*;
 
 const MAX_DURATION: usize = 86_400; // 24 hours in seconds
 
+/// `(duration in seconds, best average value)` pairs, one per bucket.
+pub type MeanMaxCurve = Vec<(usize, f32)>;
+
 lazy_static! {
     static ref POWER_CURVE_BUCKETS: Vec<usize> = {
         let mut buckets = Vec::new();
@@ -21,6 +26,11 @@ lazy_static! {
     };
 }
 
+/// Every bucket duration a curve can contain, up to 24 hours.
+pub fn power_curve_buckets() -> &'static [usize] {
+    &POWER_CURVE_BUCKETS
+}
+
 fn get_power_curve_buckets(duration: usize) -> &'static [usize] {
     let duration = duration.min(MAX_DURATION);
     let end_index = POWER_CURVE_BUCKETS
@@ -30,22 +40,152 @@ fn get_power_curve_buckets(duration: usize) -> &'static [usize] {
     &POWER_CURVE_BUCKETS[..end_index]
 }
 
-pub fn calculate_power_curve(power_data: &[u64]) -> Vec<(usize, f32)> {
-    if power_data.is_empty() {
+/// Best average value of `samples` for every bucket duration that fits into
+/// the series, which is expected to hold one sample per second. Works for any
+/// channel: power, heart rate, speed, cadence.
+///
+/// Uses a single cumulative sum so every window average is one subtraction,
+/// making each bucket linear in the length of the series.
+pub fn calculate_mean_max_curve(samples: &[f64]) -> MeanMaxCurve {
+    if samples.is_empty() {
         return vec![];
     }
-    get_power_curve_buckets(power_data.len())
+    let prefix_sums: Vec<f64> = std::iter::once(0.0)
+        .chain(samples.iter().scan(0.0, |sum, &sample| {
+            *sum += sample;
+            Some(*sum)
+        }))
+        .collect();
+    get_power_curve_buckets(samples.len())
         .into_par_iter()
-        .map(|duration| {
-            let max_avg_power = (0..power_data.len() - *duration + 1)
-                .map(|i| average(&power_data[i..i + *duration]))
-                .fold(0.0, |a: f32, b: f32| a.max(b));
-            (*duration, max_avg_power)
+        .map(|&duration| {
+            let max_sum = (duration..prefix_sums.len())
+                .map(|end| prefix_sums[end] - prefix_sums[end - duration])
+                .fold(0.0, f64::max);
+            (duration, max_sum as f32 / duration as f32)
         })
         .collect()
 }
 
-fn average(slice: &[u64]) -> f32 {
-    let sum: u64 = slice.iter().sum();
-    sum as f32 / slice.len() as f32
+/// Mean-max curve over several independent 1 Hz segments, e.g. a ride split
+/// at long pauses; no window ever spans two segments.
+pub fn calculate_segmented_mean_max_curve(segments: &[Vec<f64>]) -> MeanMaxCurve {
+    let curves: Vec<((), MeanMaxCurve)> = segments
+        .iter()
+        .map(|segment| ((), calculate_mean_max_curve(segment)))
+        .collect();
+    merge_mean_max_curves(&curves)
+        .into_iter()
+        .map(|(duration, value, _)| (duration, value))
+        .collect()
+}
+
+/// `curve` in watts per kilogram of body `weight`.
+pub fn per_kilogram(curve: &MeanMaxCurve, weight: f64) -> MeanMaxCurve {
+    curve
+        .iter()
+        .map(|&(duration, power)| (duration, (power as f64 / weight) as f32))
+        .collect()
+}
+
+/// Merges several mean-max curves bucket-by-bucket, keeping the highest value
+/// for every duration together with the source that produced it.
+pub fn merge_mean_max_curves<S: Clone>(curves: &[(S, MeanMaxCurve)]) -> Vec<(usize, f32, S)> {
+    let mut best: BTreeMap<usize, (f32, &S)> = BTreeMap::new();
+    for (source, curve) in curves {
+        for &(duration, value) in curve {
+            match best.get(&duration) {
+                Some((current, _)) if *current >= value => {}
+                _ => {
+                    best.insert(duration, (value, source));
+                }
+            }
+        }
+    }
+    best.into_iter()
+        .map(|(duration, (value, source))| (duration, value, source.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naive_power_curve(power_data: &[u64]) -> MeanMaxCurve {
+        get_power_curve_buckets(power_data.len())
+            .iter()
+            .map(|&duration| {
+                let max_avg_power = power_data
+                    .windows(duration)
+                    .map(|w| w.iter().sum::<u64>() as f32 / duration as f32)
+                    .fold(0.0, f32::max);
+                (duration, max_avg_power)
+            })
+            .collect()
+    }
+
+    fn as_samples(power_data: &[u64]) -> Vec<f64> {
+        power_data.iter().map(|&power| power as f64).collect()
+    }
+
+    fn recorded_power() -> Vec<u64> {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/data.json");
+        let data: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        data["record"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|record| record["power"]["value"].as_u64().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn matches_checked_in_power_curve() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/power_curve.json");
+        let expected: MeanMaxCurve =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(
+            calculate_mean_max_curve(&as_samples(&recorded_power())),
+            expected
+        );
+    }
+
+    #[test]
+    fn matches_naive_sliding_window() {
+        let power_data: Vec<u64> = (0..900u64).map(|i| (i * 7919) % 613).collect();
+        assert_eq!(
+            calculate_mean_max_curve(&as_samples(&power_data)),
+            naive_power_curve(&power_data)
+        );
+    }
+
+    #[test]
+    fn empty_series_has_no_curve() {
+        assert!(calculate_mean_max_curve(&[]).is_empty());
+    }
+
+    #[test]
+    fn merges_curves_keeping_the_best_source() {
+        let curves = vec![
+            ("a", vec![(1, 900.0), (5, 600.0), (60, 350.0)]),
+            ("b", vec![(1, 950.0), (5, 600.0), (300, 280.0)]),
+        ];
+        assert_eq!(
+            merge_mean_max_curves(&curves),
+            vec![
+                (1, 950.0, "b"),
+                // ties keep the earlier source
+                (5, 600.0, "a"),
+                (60, 350.0, "a"),
+                (300, 280.0, "b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn merging_nothing_is_empty() {
+        assert!(merge_mean_max_curves::<&str>(&[]).is_empty());
+        assert!(merge_mean_max_curves(&[("a", Vec::new())]).is_empty());
+    }
 }
@@ -0,0 +1,156 @@
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::power_curve::MeanMaxCurve;
+
+/// Durations (in seconds) for which personal records are tracked.
+pub const RECORD_DURATIONS: [usize; 10] = [5, 15, 30, 60, 120, 300, 600, 1200, 1800, 3600];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordPeriod {
+    AllTime,
+    Last42Days,
+    Last90Days,
+    ThisYear,
+}
+
+impl RecordPeriod {
+    pub const ALL: [RecordPeriod; 4] = [
+        RecordPeriod::AllTime,
+        RecordPeriod::Last42Days,
+        RecordPeriod::Last90Days,
+        RecordPeriod::ThisYear,
+    ];
+
+    /// Earliest start time an activity may have to count towards this period,
+    /// relative to an activity starting at `at`.
+    pub fn since(&self, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            RecordPeriod::AllTime => None,
+            RecordPeriod::Last42Days => Some(at - Duration::days(42)),
+            RecordPeriod::Last90Days => Some(at - Duration::days(90)),
+            RecordPeriod::ThisYear => Utc.with_ymd_and_hms(at.year(), 1, 1, 0, 0, 0).single(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PersonalRecord {
+    pub user_id: String,
+    pub activity_id: String,
+    pub start_time: DateTime<Utc>,
+    pub period: RecordPeriod,
+    pub duration: usize,
+    pub power: f32,
+    /// `power` per kilogram of the athlete's weight at the time.
+    #[serde(default)]
+    pub power_wkg: Option<f32>,
+    pub previous_power: Option<f32>,
+}
+
+fn power_at(curve: &[(usize, f32)], duration: usize) -> Option<f32> {
+    curve
+        .binary_search_by_key(&duration, |&(d, _)| d)
+        .ok()
+        .map(|i| curve[i].1)
+}
+
+/// Compares `curve` of an activity starting at `start_time` against the
+/// curves of earlier activities and returns every period/duration it beats.
+/// A user's first activity sets no records, as there is nothing to beat.
+/// `activity_id` is left empty, as it is only known once the activity is stored.
+/// Records are ranked in absolute watts; `weight` only adds the W/kg value.
+pub fn detect_personal_records(
+    user_id: &str,
+    start_time: DateTime<Utc>,
+    curve: &[(usize, f32)],
+    weight: Option<f64>,
+    history: &[(DateTime<Utc>, MeanMaxCurve)],
+) -> Vec<PersonalRecord> {
+    let mut records = Vec::new();
+    if history.is_empty() {
+        return records;
+    }
+    for period in RecordPeriod::ALL {
+        let since = period.since(start_time);
+        let in_period: Vec<&MeanMaxCurve> = history
+            .iter()
+            .filter(|(at, _)| since.is_none_or(|since| *at >= since) && *at <= start_time)
+            .map(|(_, curve)| curve)
+            .collect();
+        for duration in RECORD_DURATIONS {
+            let Some(power) = power_at(curve, duration).filter(|p| *p > 0.0) else {
+                continue;
+            };
+            let previous_power = in_period
+                .iter()
+                .filter_map(|c| power_at(c, duration))
+                .fold(None, |best: Option<f32>, p| {
+                    Some(best.map_or(p, |b| b.max(p)))
+                });
+            if previous_power.is_none_or(|previous| power > previous) {
+                records.push(PersonalRecord {
+                    user_id: user_id.to_owned(),
+                    activity_id: String::new(),
+                    start_time,
+                    period,
+                    duration,
+                    power,
+                    power_wkg: weight.map(|weight| (power as f64 / weight) as f32),
+                    previous_power,
+                });
+            }
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, day, 8, 0, 0).unwrap()
+    }
+
+    fn curve(power: f32) -> MeanMaxCurve {
+        RECORD_DURATIONS.iter().map(|&d| (d, power)).collect()
+    }
+
+    #[test]
+    fn first_activity_sets_no_records() {
+        assert!(detect_personal_records("u", at(10), &curve(300.0), None, &[]).is_empty());
+    }
+
+    #[test]
+    fn records_every_period_and_duration_beaten() {
+        let history = vec![(at(1), curve(250.0))];
+        let records = detect_personal_records("u", at(10), &curve(300.0), Some(75.0), &history);
+        assert_eq!(
+            records.len(),
+            RecordPeriod::ALL.len() * RECORD_DURATIONS.len()
+        );
+        assert!(records.iter().all(|r| r.previous_power == Some(250.0)));
+        assert_eq!(records[0].power_wkg, Some(4.0));
+    }
+
+    #[test]
+    fn ties_and_weaker_efforts_are_not_records() {
+        let history = vec![(at(1), curve(300.0))];
+        assert!(detect_personal_records("u", at(10), &curve(300.0), None, &history).is_empty());
+        assert!(detect_personal_records("u", at(10), &curve(200.0), None, &history).is_empty());
+    }
+
+    #[test]
+    fn old_efforts_only_count_for_longer_periods() {
+        let old = Utc.with_ymd_and_hms(2023, 11, 1, 8, 0, 0).unwrap();
+        let history = vec![(old, curve(400.0))];
+        let records = detect_personal_records("u", at(10), &curve(300.0), None, &history);
+        let periods: Vec<RecordPeriod> = records.iter().map(|r| r.period).collect();
+        assert!(periods.contains(&RecordPeriod::Last42Days));
+        assert!(periods.contains(&RecordPeriod::Last90Days));
+        assert!(periods.contains(&RecordPeriod::ThisYear));
+        assert!(!periods.contains(&RecordPeriod::AllTime));
+    }
+}
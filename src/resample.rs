@@ -0,0 +1,202 @@
+use std::iter::repeat_n;
+
+use fitparser::profile::MesgNum;
+use serde::{Deserialize, Serialize};
+
+use crate::structures::FitDataMap;
+
+/// Pauses longer than this many seconds split the series when breaking at gaps.
+pub const DEFAULT_MAX_GAP: i64 = 30;
+
+/// Gaps longer than this many seconds start a new segment in every mode
+/// rather than being filled, so a corrupt timestamp can't blow up the series.
+const MAX_FILLED_GAP: i64 = 3600;
+/// Seconds filled in across all gaps of a series, after which every further
+/// gap starts a new segment.
+const MAX_FILLED_TOTAL: usize = 86_400;
+
+/// How missing seconds between two recorded samples are filled in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GapHandling {
+    /// Every missing second counts as a zero sample.
+    #[default]
+    ZeroFill,
+    /// Every missing second repeats the previous sample.
+    HoldLast,
+    /// Gaps longer than `max_gap` start a new segment so no window spans the
+    /// pause; shorter gaps repeat the previous sample.
+    Break,
+}
+
+/// `(unix timestamp, value)` of every `Record` message carrying a timestamp,
+/// with `0` standing in for records that lack `field`.
+pub fn record_samples(data: &FitDataMap, field: &str) -> Vec<(i64, f64)> {
+    data.get(&MesgNum::Record)
+        .map(|records| {
+            records
+                .iter()
+                .filter_map(|entry| {
+                    let timestamp = entry.get("timestamp")?.as_timestamp()?.timestamp();
+                    let value = entry
+                        .get(field)
+                        .and_then(|v| v.as_f64())
+                        .unwrap_or_default();
+                    Some((timestamp, value))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Turns timestamped samples into contiguous 1 Hz segments. Duplicate
+/// timestamps keep the latest sample and out-of-order samples are dropped.
+/// Besides `GapHandling::Break`, only gaps beyond `MAX_FILLED_GAP` or the
+/// total fill budget yield more than one segment.
+pub fn resample<T: Copy + Default>(
+    samples: &[(i64, T)],
+    gap_handling: GapHandling,
+    max_gap: i64,
+) -> Vec<Vec<T>> {
+    resample_timed(samples, gap_handling, max_gap)
+        .into_iter()
+        .map(|(_, segment)| segment)
+        .collect()
+}
+
+/// `resample` with the unix timestamp of every segment's first sample, for
+/// callers that need to place offsets within a segment in time.
+pub fn resample_timed<T: Copy + Default>(
+    samples: &[(i64, T)],
+    gap_handling: GapHandling,
+    max_gap: i64,
+) -> Vec<(i64, Vec<T>)> {
+    let mut segments = Vec::new();
+    let mut current: Vec<T> = Vec::new();
+    let mut current_start = samples.first().map_or(0, |sample| sample.0);
+    let mut last: Option<(i64, T)> = None;
+    let mut filled = 0;
+
+    for &(timestamp, value) in samples {
+        if let Some((last_timestamp, last_value)) = last {
+            let delta = timestamp - last_timestamp;
+            if delta < 0 {
+                continue;
+            }
+            if delta == 0 {
+                if let Some(sample) = current.last_mut() {
+                    *sample = value;
+                }
+                last = Some((timestamp, value));
+                continue;
+            }
+            let missing = (delta - 1) as usize;
+            let too_long = delta > MAX_FILLED_GAP || filled + missing > MAX_FILLED_TOTAL;
+            match gap_handling {
+                _ if too_long || (gap_handling == GapHandling::Break && delta > max_gap) => {
+                    segments.push((current_start, std::mem::take(&mut current)));
+                    current_start = timestamp;
+                }
+                GapHandling::ZeroFill => {
+                    current.extend(repeat_n(T::default(), missing));
+                    filled += missing;
+                }
+                GapHandling::HoldLast | GapHandling::Break => {
+                    current.extend(repeat_n(last_value, missing));
+                    filled += missing;
+                }
+            }
+        }
+        current.push(value);
+        last = Some((timestamp, value));
+    }
+
+    if !current.is_empty() {
+        segments.push((current_start, current));
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [GapHandling; 3] = [
+        GapHandling::ZeroFill,
+        GapHandling::HoldLast,
+        GapHandling::Break,
+    ];
+
+    #[test]
+    fn fills_gaps_per_mode() {
+        let samples = [(0, 1.0), (1, 2.0), (4, 3.0)];
+        assert_eq!(
+            resample(&samples, GapHandling::ZeroFill, 2),
+            vec![vec![1.0, 2.0, 0.0, 0.0, 3.0]]
+        );
+        assert_eq!(
+            resample(&samples, GapHandling::HoldLast, 2),
+            vec![vec![1.0, 2.0, 2.0, 2.0, 3.0]]
+        );
+        assert_eq!(
+            resample(&samples, GapHandling::Break, 2),
+            vec![vec![1.0, 2.0], vec![3.0]]
+        );
+        assert_eq!(
+            resample(&samples, GapHandling::Break, 3),
+            vec![vec![1.0, 2.0, 2.0, 2.0, 3.0]]
+        );
+    }
+
+    #[test]
+    fn duplicate_timestamps_keep_the_latest_sample() {
+        let samples = [(0, 1.0), (1, 2.0), (1, 5.0), (2, 3.0)];
+        for mode in MODES {
+            assert_eq!(resample(&samples, mode, 2), vec![vec![1.0, 5.0, 3.0]]);
+        }
+    }
+
+    #[test]
+    fn backwards_timestamps_are_dropped() {
+        let samples = [(10, 1.0), (11, 2.0), (5, 9.0), (12, 3.0)];
+        for mode in MODES {
+            assert_eq!(resample(&samples, mode, 2), vec![vec![1.0, 2.0, 3.0]]);
+        }
+    }
+
+    #[test]
+    fn empty_input_has_no_segments() {
+        for mode in MODES {
+            assert!(resample::<f64>(&[], mode, 2).is_empty());
+        }
+    }
+
+    #[test]
+    fn huge_gaps_split_instead_of_filling() {
+        let samples = [(0, 1.0), (1_700_000_000, 2.0)];
+        for mode in MODES {
+            assert_eq!(
+                resample(&samples, mode, DEFAULT_MAX_GAP),
+                vec![vec![1.0], vec![2.0]]
+            );
+        }
+    }
+
+    #[test]
+    fn segments_keep_their_start_timestamp() {
+        let samples = [(100, 1.0), (101, 2.0), (5000, 3.0), (5002, 4.0)];
+        assert_eq!(
+            resample_timed(&samples, GapHandling::ZeroFill, DEFAULT_MAX_GAP),
+            vec![(100, vec![1.0, 2.0]), (5000, vec![3.0, 0.0, 4.0])]
+        );
+    }
+
+    #[test]
+    fn fill_budget_is_shared_across_gaps() {
+        let samples: Vec<(i64, f64)> = (0..30).map(|i| (i * MAX_FILLED_GAP, 1.0)).collect();
+        let segments = resample(&samples, GapHandling::ZeroFill, DEFAULT_MAX_GAP);
+        let total: usize = segments.iter().map(Vec::len).sum();
+        assert!(total <= samples.len() + MAX_FILLED_TOTAL);
+        assert!(segments.len() > 1);
+    }
+}
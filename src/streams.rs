@@ -0,0 +1,169 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::structures::Record;
+
+/// Degrees per semicircle, the unit FIT positions are recorded in.
+const DEGREES_PER_SEMICIRCLE: f64 = 180.0 / 2_147_483_648.0;
+/// Samples per stored document, about 5.5 hours at 1 Hz and well under
+/// MongoDB's 16 MB document limit.
+pub const STREAM_CHUNK_SAMPLES: usize = 20_000;
+
+/// The `Record` stream of an activity stored column by column, so field names
+/// and units aren't repeated for every sample. Every channel has one entry
+/// per sample in `time`. Long activities are stored in several chunks of at
+/// most `STREAM_CHUNK_SAMPLES` samples.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RecordStreams {
+    pub activity_id: String,
+    pub user_id: String,
+    pub start_time: Option<DateTime<Utc>>,
+    /// Index of this chunk of the activity's streams.
+    #[serde(default)]
+    pub chunk: usize,
+    /// Seconds since `start_time`.
+    pub time: Vec<i64>,
+    pub power: Vec<u16>,
+    pub heart_rate: Vec<u8>,
+    pub cadence: Vec<u8>,
+    pub speed: Vec<f64>,    // m/s
+    pub altitude: Vec<f64>, // m
+    pub distance: Vec<f64>, // m
+    /// Positions in degrees, `None` for samples without a GPS fix.
+    pub latitude: Vec<Option<f64>>,
+    pub longitude: Vec<Option<f64>>,
+}
+
+fn degrees(semicircles: i32) -> Option<f64> {
+    (semicircles != 0).then_some(semicircles as f64 * DEGREES_PER_SEMICIRCLE)
+}
+
+impl RecordStreams {
+    /// `activity_id` is left empty, as it is only known once the activity is stored.
+    pub fn from_records(user_id: &str, records: &[Record]) -> Self {
+        let start_time = records.first().map(|record| record.timestamp);
+        let offset = |record: &Record| {
+            start_time.map_or(0, |start| (record.timestamp - start).num_seconds())
+        };
+        RecordStreams {
+            activity_id: String::new(),
+            user_id: user_id.to_owned(),
+            start_time,
+            chunk: 0,
+            time: records.iter().map(offset).collect(),
+            power: records.iter().map(|r| r.power.value).collect(),
+            heart_rate: records.iter().map(|r| r.heart_rate.value).collect(),
+            cadence: records.iter().map(|r| r.cadence.value).collect(),
+            speed: records.iter().map(|r| r.enhanced_speed.value).collect(),
+            altitude: records.iter().map(|r| r.enhanced_altitude.value).collect(),
+            distance: records.iter().map(|r| r.distance.value).collect(),
+            latitude: records
+                .iter()
+                .map(|r| degrees(r.position_lat.value))
+                .collect(),
+            longitude: records
+                .iter()
+                .map(|r| degrees(r.position_long.value))
+                .collect(),
+        }
+    }
+
+    /// Splits the streams into chunks of at most `STREAM_CHUNK_SAMPLES`
+    /// samples. Offsets in `time` stay relative to `start_time`.
+    pub fn chunks(&self) -> Vec<RecordStreams> {
+        let len = self.time.len();
+        if len == 0 {
+            return vec![self.clone()];
+        }
+        (0..len)
+            .step_by(STREAM_CHUNK_SAMPLES)
+            .enumerate()
+            .map(|(chunk, start)| {
+                let range = start..(start + STREAM_CHUNK_SAMPLES).min(len);
+                RecordStreams {
+                    activity_id: self.activity_id.clone(),
+                    user_id: self.user_id.clone(),
+                    start_time: self.start_time,
+                    chunk,
+                    time: self.time[range.clone()].to_vec(),
+                    power: self.power[range.clone()].to_vec(),
+                    heart_rate: self.heart_rate[range.clone()].to_vec(),
+                    cadence: self.cadence[range.clone()].to_vec(),
+                    speed: self.speed[range.clone()].to_vec(),
+                    altitude: self.altitude[range.clone()].to_vec(),
+                    distance: self.distance[range.clone()].to_vec(),
+                    latitude: self.latitude[range.clone()].to_vec(),
+                    longitude: self.longitude[range].to_vec(),
+                }
+            })
+            .collect()
+    }
+
+    /// Joins the stored chunks of one activity's streams back together, or
+    /// `None` when there are none.
+    pub fn merge(mut chunks: Vec<RecordStreams>) -> Option<RecordStreams> {
+        chunks.sort_by_key(|chunk| chunk.chunk);
+        let mut chunks = chunks.into_iter();
+        let mut merged = chunks.next()?;
+        for chunk in chunks {
+            merged.time.extend(chunk.time);
+            merged.power.extend(chunk.power);
+            merged.heart_rate.extend(chunk.heart_rate);
+            merged.cadence.extend(chunk.cadence);
+            merged.speed.extend(chunk.speed);
+            merged.altitude.extend(chunk.altitude);
+            merged.distance.extend(chunk.distance);
+            merged.latitude.extend(chunk.latitude);
+            merged.longitude.extend(chunk.longitude);
+        }
+        Some(merged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn streams(samples: usize) -> RecordStreams {
+        RecordStreams {
+            activity_id: "activity".to_owned(),
+            time: (0..samples as i64).collect(),
+            power: (0..samples).map(|i| (i % 400) as u16).collect(),
+            heart_rate: vec![140; samples],
+            cadence: vec![90; samples],
+            speed: vec![8.5; samples],
+            altitude: vec![120.0; samples],
+            distance: (0..samples).map(|i| i as f64 * 8.5).collect(),
+            latitude: vec![Some(51.5); samples],
+            longitude: vec![None; samples],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chunks_split_long_streams_and_merge_back() {
+        let samples = 2 * STREAM_CHUNK_SAMPLES + 5;
+        let chunks = streams(samples).chunks();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[2].chunk, 2);
+        assert_eq!(
+            chunks[2].time,
+            (2 * STREAM_CHUNK_SAMPLES as i64..samples as i64).collect::<Vec<_>>()
+        );
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.power.len() == chunk.time.len()));
+
+        let merged = RecordStreams::merge(chunks.into_iter().rev().collect()).unwrap();
+        assert_eq!(merged.time, streams(samples).time);
+        assert_eq!(merged.power, streams(samples).power);
+        assert_eq!(merged.longitude.len(), samples);
+    }
+
+    #[test]
+    fn short_streams_are_one_chunk() {
+        assert_eq!(streams(10).chunks().len(), 1);
+        assert_eq!(streams(0).chunks().len(), 1);
+        assert!(RecordStreams::merge(Vec::new()).is_none());
+    }
+}
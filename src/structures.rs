@@ -1,12 +1,19 @@
-use std::{
-    collections::{BTreeMap, HashMap},
-    convert, fmt,
-};
+#![allow(dead_code)]
+
+use std::{collections::BTreeMap, convert, fmt};
 
 use chrono::{DateTime, Utc};
 use fitparser::{profile::MesgNum, FitDataField, FitDataRecord, Value};
 use serde::{Deserialize, Serialize};
 
+use crate::compliance::ComplianceReport;
+use crate::laps::LapStats;
+use crate::metrics::ActivityMetrics;
+use crate::power_curve::MeanMaxCurve;
+use crate::summary::ActivitySummary;
+use crate::wbal::WPrimeBalance;
+use crate::zones::TimeInZones;
+
 pub type FitDataMap = BTreeMap<MesgNum, Vec<BTreeMap<String, ValueWithUnitsName>>>;
 
 #[derive(Clone, Debug, Serialize)]
@@ -15,11 +22,40 @@ struct FitDataList {
     fields: BTreeMap<String, ValueWithUnitsName>,
 }
 
+/// Version of the stored activity document layout. Documents without it
+/// predate the typed `Activity` model and only carry `fit_data`. Version 3
+/// adds `content_hash` and `file_fingerprint`.
+pub const SCHEMA_VERSION: u32 = 3;
+
 #[derive(Clone, Debug, Serialize)]
 pub struct MongoSchema {
+    pub schema_version: u32,
     pub user_id: String,
-    pub fit_data: FitDataMap,
-    pub power_curve: Vec<(usize, f32)>,
+    /// Hex SHA-256 of the uploaded file.
+    pub content_hash: Option<String>,
+    /// Identifies the recording across re-exports, see `FileId::fingerprint`.
+    pub file_fingerprint: Option<String>,
+    pub activity: Activity,
+    /// The untyped messages without `Record`s, only kept when asked for on
+    /// upload.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fit_data: Option<FitDataMap>,
+    pub power_curve: MeanMaxCurve,
+    /// `power_curve` divided by the athlete's weight, when known.
+    pub power_curve_wkg: Option<MeanMaxCurve>,
+    pub hr_curve: MeanMaxCurve,
+    pub speed_curve: MeanMaxCurve,
+    pub cadence_curve: MeanMaxCurve,
+    pub pace_curve: Option<MeanMaxCurve>,
+    pub summary: ActivitySummary,
+    pub metrics: ActivityMetrics,
+    pub w_prime_balance: Option<WPrimeBalance>,
+    pub time_in_zones: TimeInZones,
+    pub laps: Vec<LapStats>,
+    /// Efforts detected from the power series, independent of the laps.
+    pub intervals: Vec<Split>,
+    /// How closely the planned workout in the file was followed.
+    pub compliance: Option<ComplianceReport>,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -28,6 +64,26 @@ pub struct ValueWithUnitsName {
     pub units: String,
 }
 
+impl ValueWithUnitsName {
+    pub fn as_f64(&self) -> Option<f64> {
+        self.value.to_owned().try_into().ok()
+    }
+
+    pub fn as_string(&self) -> Option<String> {
+        match &self.value {
+            Value::String(s) => Some(s.to_owned()),
+            _ => None,
+        }
+    }
+
+    pub fn as_timestamp(&self) -> Option<DateTime<Utc>> {
+        match &self.value {
+            Value::Timestamp(t) => Some(t.with_timezone(&Utc)),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for ValueWithUnitsName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if self.units.is_empty() {
@@ -61,7 +117,7 @@ impl FitDataList {
 }
 
 pub fn merge_by_kind(mut map: FitDataMap, record: fitparser::FitDataRecord) -> FitDataMap {
-    map.entry(record.kind()).or_insert_with(Vec::new).push(
+    map.entry(record.kind()).or_default().push(
         record
             .into_vec()
             .into_iter()
@@ -76,7 +132,7 @@ macro_rules! get_field_from_iter {
         $iter
             .find(|f| f.name() == $field_name)
             .and_then(|f| {
-                let value: $default_type = f.value().to_owned().try_into().unwrap();
+                let value: $default_type = f.value().to_owned().try_into().ok()?;
                 let units = f.units().to_owned();
                 Some((value as $output_type, units))
             })
@@ -110,12 +166,18 @@ where
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Split {
     pub start_time: chrono::DateTime<chrono::Utc>,
     pub end_time: chrono::DateTime<chrono::Utc>,
     pub name: Option<String>,
-    // ... other fields specific to the type of split ...
+    pub duration: usize, // seconds
+    pub avg_power: f64,
+    pub max_power: f64,
+    pub normalized_power: Option<f64>,
+    pub intensity_factor: Option<f64>,
+    pub avg_heart_rate: Option<f64>,
+    pub work_kj: f64,
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -135,38 +197,75 @@ pub struct Record {
 }
 
 impl Record {
-    pub fn from_fitentry(entry: &FitDataRecord) -> Self {
-        let mut fields = entry.fields().iter();
-        Record {
-            cadence: get_field_from_iter!(fields, "cadence", i64, u8, value_to_i64, "rpm"),
+    /// `None` for a record without a timestamp, which can't be placed in the
+    /// activity's streams.
+    pub fn from_fitentry(entry: &FitDataRecord) -> Option<Self> {
+        let timestamp = entry
+            .fields()
+            .iter()
+            .find(|f| f.name() == "timestamp")
+            .and_then(|f| match f.value().to_owned() {
+                Value::Timestamp(t) => Some(t.into()),
+                _ => None,
+            })?;
+        // older devices only record the 16-bit speed field
+        let speed_field = if entry.fields().iter().any(|f| f.name() == "enhanced_speed") {
+            "enhanced_speed"
+        } else {
+            "speed"
+        };
+        Some(Record {
+            cadence: get_field_from_iter!(
+                entry.fields().iter(),
+                "cadence",
+                i64,
+                u8,
+                value_to_i64,
+                "rpm"
+            ),
             accumulated_power: get_field_from_iter!(
-                fields,
+                entry.fields().iter(),
                 "accumulated_power",
                 i64,
                 u32,
                 value_to_i64,
                 "W"
             ),
-            power: get_field_from_iter!(fields, "power", i64, u16, value_to_i64, "W"),
-            timestamp: fields
-                .find(|f| f.name() == "timestamp")
-                .and_then(|f| match f.value().to_owned() {
-                    Value::Timestamp(t) => Some(t.into()),
-                    _ => None,
-                })
-                .unwrap_or_else(|| Utc::now()),
+            power: get_field_from_iter!(
+                entry.fields().iter(),
+                "power",
+                i64,
+                u16,
+                value_to_i64,
+                "W"
+            ),
+            timestamp,
             fractional_cadence: get_field_from_iter!(
-                fields,
+                entry.fields().iter(),
                 "fractional_cadence",
                 f64,
                 f64,
                 value_to_f64,
                 "rpm"
             ),
-            distance: get_field_from_iter!(fields, "distance", f64, f64, value_to_f64, "m"),
-            heart_rate: get_field_from_iter!(fields, "heart_rate", i64, u8, value_to_i64, "bpm"),
+            distance: get_field_from_iter!(
+                entry.fields().iter(),
+                "distance",
+                f64,
+                f64,
+                value_to_f64,
+                "m"
+            ),
+            heart_rate: get_field_from_iter!(
+                entry.fields().iter(),
+                "heart_rate",
+                i64,
+                u8,
+                value_to_i64,
+                "bpm"
+            ),
             position_long: get_field_from_iter!(
-                fields,
+                entry.fields().iter(),
                 "position_long",
                 i64,
                 i32,
@@ -174,7 +273,7 @@ impl Record {
                 "semicircles"
             ),
             position_lat: get_field_from_iter!(
-                fields,
+                entry.fields().iter(),
                 "position_lat",
                 i64,
                 i32,
@@ -182,47 +281,84 @@ impl Record {
                 "semicircles"
             ),
             enhanced_altitude: get_field_from_iter!(
-                fields,
+                entry.fields().iter(),
                 "enhanced_altitude",
                 f64,
                 f64,
                 value_to_f64,
                 "m"
             ),
-            gps_accuracy: get_field_from_iter!(fields, "gps_accuracy", i64, u8, value_to_i64, "m"),
+            gps_accuracy: get_field_from_iter!(
+                entry.fields().iter(),
+                "gps_accuracy",
+                i64,
+                u8,
+                value_to_i64,
+                "m"
+            ),
             enhanced_speed: get_field_from_iter!(
-                fields,
-                "enhanced_speed",
+                entry.fields().iter(),
+                speed_field,
                 f64,
                 f64,
                 value_to_f64,
                 "m/s"
             ),
+        })
+    }
+}
+
+/// The messages of a FIT activity file we make use of, typed.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Activity {
+    pub file_id: Option<FileId>,
+    pub devices: Vec<DeviceInfo>,
+    pub sport: Option<Sport>,
+    pub sessions: Vec<Session>,
+    pub laps: Vec<Lap>,
+    /// Stored column by column in the `streams` collection instead.
+    #[serde(skip_serializing)]
+    pub records: Vec<Record>,
+    pub events: Vec<Event>,
+    pub zones_target: Option<ZonesTarget>,
+    pub time_in_zones: Vec<TimeInZone>,
+    pub user_profile: Option<FitUserProfile>,
+    pub workout: Option<Workout>,
+    pub workout_steps: Vec<WorkoutStep>,
+}
+
+impl Activity {
+    pub fn from_records(records: impl IntoIterator<Item = FitDataRecord>) -> Self {
+        let mut activity = Activity::default();
+        for record in records {
+            match FitEntry::new(record) {
+                FitEntry::FileId(file_id) => activity.file_id = Some(file_id),
+                FitEntry::DeviceInfo(device) => activity.devices.push(device),
+                FitEntry::Sport(sport) => activity.sport = Some(sport),
+                FitEntry::Session(session) => activity.sessions.push(session),
+                FitEntry::Lap(lap) => activity.laps.push(lap),
+                FitEntry::Record(record) => activity.records.push(record),
+                FitEntry::Event(event) => activity.events.push(event),
+                FitEntry::ZonesTarget(zones) => activity.zones_target = Some(zones),
+                FitEntry::TimeInZone(zones) => activity.time_in_zones.push(zones),
+                FitEntry::UserProfile(profile) => activity.user_profile = Some(profile),
+                FitEntry::Workout(workout) => activity.workout = Some(workout),
+                FitEntry::WorkoutStep(step) => activity.workout_steps.push(step),
+                _ => {}
+            }
         }
+        activity
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Serialize, Debug)]
 pub enum FitEntry {
-    FileId {
-        manufacturer: String,
-        product_name: String,
-        serial_number: u32,          // UInt32z
-        time_created: DateTime<Utc>, // Timestamp
-        file_type: String,
-    },
+    FileId(FileId),
     FileCreator {
         software_version: u16,
     },
-    DeviceInfo {
-        descriptor: String,
-        device_index: String,
-        manufacturer: String,
-        product_name: String,
-        serial_number: u32, // UInt32z
-        source_type: String,
-        timestamp: DateTime<Utc>, // Timestamp
-    },
+    DeviceInfo(DeviceInfo),
     DeveloperDataId {
         application_id: Vec<u8>,
         application_version: u32,
@@ -235,115 +371,16 @@ pub enum FitEntry {
         field_name: String,
         fit_base_type_id: String,
     },
-    Workout {
-        capabilities: String,
-        num_valid_steps: u16,
-        sport: String,
-        wkt_name: String,
-    },
-    WorkoutStep {
-        duration_time: ValueWithUnit<f64>, // Float64
-        duration_type: String,
-        intensity: String,
-        message_index: i64, // SInt64
-        target_type: String,
-        target_value: u32,
-    },
-    Event {
-        event: String,
-        event_group: u8,
-        event_type: String,
-        timer_trigger: String,
-        timestamp: DateTime<Utc>, // Timestamp
-    },
-    Sport {
-        name: String,
-        sport: String,
-        sub_sport: String,
-    },
-    ZonesTarget {
-        functional_threshold_power: ValueWithUnit<f64>,
-        pwr_calc_type: String,
-    },
+    Workout(Workout),
+    WorkoutStep(WorkoutStep),
+    Event(Event),
+    Sport(Sport),
+    ZonesTarget(ZonesTarget),
     Record(Record),
-    Lap {
-        avg_cadence: ValueWithUnit<f64>,
-        avg_fractional_cadence: ValueWithUnit<f64>, // Float64
-        avg_heart_rate: ValueWithUnit<u8>,
-        avg_power: ValueWithUnit<u16>,             // UInt16
-        enhanced_avg_speed: ValueWithUnit<f64>,    // Float64
-        enhanced_max_altitude: ValueWithUnit<f64>, // Float64
-        enhanced_max_speed: ValueWithUnit<f64>,    // Float64
-        enhanced_min_altitude: ValueWithUnit<f64>, // Float64
-        event: String,
-        event_type: String,
-        intensity: String,
-        max_cadence: ValueWithUnit<f64>,
-        max_fractional_cadence: ValueWithUnit<f64>, // Float64
-        max_heart_rate: ValueWithUnit<u8>,
-        max_power: ValueWithUnit<u16>, // UInt16
-        message_index: i64,            // SInt64
-        min_heart_rate: ValueWithUnit<u8>,
-        sport: String,
-        start_time: DateTime<Utc>, // Timestamp
-        sub_sport: String,
-        timestamp: DateTime<Utc>,               // Timestamp
-        total_calories: ValueWithUnit<u16>,     // UInt16
-        total_distance: ValueWithUnit<f64>,     // Float64
-        total_elapsed_time: ValueWithUnit<f64>, // Float64
-        total_timer_time: ValueWithUnit<f64>,   // Float64
-        wkt_step_index: i64,                    // SInt64
-    },
-    TimeInZone {
-        functional_threshold_power: ValueWithUnit<f64>,
-        hr_calc_type: String,
-        hr_zone_high_boundary: ValueWithUnit<f64>,
-        max_heart_rate: ValueWithUnit<f64>,
-        power_zone_high_boundary: ValueWithUnit<f64>,
-        pwr_calc_type: String,
-        reference_index: i64, // SInt64
-        reference_mesg: String,
-        resting_heart_rate: ValueWithUnit<f64>,
-        time_in_hr_zone: Vec<f64>,    // Array of Float64
-        time_in_power_zone: Vec<f64>, // Array of Float64
-        timestamp: DateTime<Utc>,     // Timestamp
-    },
-    Session {
-        avg_cadence: ValueWithUnit<f64>,
-        avg_fractional_cadence: ValueWithUnit<f64>, // Float64
-        avg_heart_rate: ValueWithUnit<f64>,
-        avg_power: ValueWithUnit<f64>,             // UInt16
-        avg_temperature: ValueWithUnit<f64>,       // SInt8
-        enhanced_avg_altitude: ValueWithUnit<f64>, // Float64
-        enhanced_avg_speed: ValueWithUnit<f64>,    // Float64
-        enhanced_max_altitude: ValueWithUnit<f64>, // Float64
-        enhanced_max_speed: ValueWithUnit<f64>,    // Float64
-        enhanced_min_altitude: ValueWithUnit<f64>, // Float64
-        event_type: String,
-        first_lap_index: ValueWithUnit<f64>, // UInt16
-        max_cadence: ValueWithUnit<f64>,
-        max_fractional_cadence: ValueWithUnit<f64>, // Float64
-        max_heart_rate: ValueWithUnit<f64>,
-        max_power: ValueWithUnit<f64>, // UInt16
-        message_index: i64,            // SInt64
-        min_heart_rate: ValueWithUnit<f64>,
-        nec_lat: ValueWithUnit<f64>,  // SInt32
-        nec_long: ValueWithUnit<f64>, // SInt32
-        num_laps: ValueWithUnit<f64>, // UInt16
-        sport: String,
-        start_time: DateTime<Utc>, // Timestamp
-        sub_sport: String,
-        swc_lat: ValueWithUnit<f64>,            // SInt32
-        swc_long: ValueWithUnit<f64>,           // SInt32
-        threshold_power: ValueWithUnit<f64>,    // UInt16
-        timestamp: DateTime<Utc>,               // Timestamp
-        total_ascent: ValueWithUnit<f64>,       // UInt16
-        total_calories: ValueWithUnit<f64>,     // UInt16
-        total_distance: ValueWithUnit<f64>,     // Float64
-        total_elapsed_time: ValueWithUnit<f64>, // Float64
-        total_timer_time: ValueWithUnit<f64>,   // Float64
-        trigger: String,
-    },
+    Lap(Lap),
+    TimeInZone(TimeInZone),
+    UserProfile(FitUserProfile),
+    Session(Session),
     Activity {
         event: String,
         event_type: String,
@@ -373,11 +410,21 @@ fn value_to_f64(field: &FitDataField) -> Option<f64> {
 
 fn value_to_units(field: &FitDataField) -> Option<ValueWithUnit<f64>> {
     Some(ValueWithUnit {
-        value: field.value().to_owned().try_into().unwrap(),
+        value: field.value().to_owned().try_into().ok()?,
         units: field.units().to_owned(),
     })
 }
 
+fn value_to_f64_vec(field: &FitDataField) -> Option<Vec<f64>> {
+    match field.value() {
+        Value::Array(values) => values
+            .iter()
+            .map(|v| v.to_owned().try_into().ok())
+            .collect(),
+        value => value.to_owned().try_into().ok().map(|v| vec![v]),
+    }
+}
+
 fn to_timestamp(field: &FitDataField) -> Option<DateTime<Utc>> {
     match field.value().to_owned() {
         Value::Timestamp(t) => Some(t.into()),
@@ -397,7 +444,7 @@ macro_rules! extract_value_with_unit {
     ($record:expr, $field_name:expr, $try_into_type:ty, $output_type:ty, $default_unit:expr) => {{
         FitEntry::get_field($record, $field_name)
             .and_then(|f| {
-                let value: $try_into_type = f.value().to_owned().try_into().unwrap();
+                let value: $try_into_type = f.value().to_owned().try_into().ok()?;
                 let units = f.units().to_owned();
                 Some(ValueWithUnit {
                     value: value as $output_type,
@@ -411,67 +458,564 @@ macro_rules! extract_value_with_unit {
     }};
 }
 
-macro_rules! extract_fields {
-    ($record:expr, [$(($field_name:expr, $default_type:ty, $transform:expr)),*]) => {{
-        let mut result = ($(<$default_type>::default()),*);
+#[derive(Serialize, Debug, Clone)]
+pub struct TimeInZone {
+    pub functional_threshold_power: ValueWithUnit<f64>,
+    pub hr_calc_type: String,
+    pub hr_zone_high_boundary: Vec<f64>, // Array of UInt8
+    pub max_heart_rate: ValueWithUnit<f64>,
+    pub power_zone_high_boundary: Vec<f64>, // Array of UInt16
+    pub pwr_calc_type: String,
+    pub reference_index: i64, // SInt64
+    pub reference_mesg: String,
+    pub resting_heart_rate: ValueWithUnit<f64>,
+    pub time_in_hr_zone: Vec<f64>,    // Array of Float64
+    pub time_in_power_zone: Vec<f64>, // Array of Float64
+    pub timestamp: DateTime<Utc>,     // Timestamp
+}
 
-        for field in $record.fields() {
-            match field.name() {
-                $(
-                    $field_name => {
-                        let transformed = $transform(field).unwrap_or_else(|| <$default_type>::default());
-                        result.$n = transformed;
-                    }
-                )*
-                _ => {}
-            }
+impl TimeInZone {
+    pub fn from_fitentry(record: &FitDataRecord) -> Self {
+        TimeInZone {
+            functional_threshold_power: extract_value_with_unit!(
+                record,
+                "functional_threshold_power",
+                f64,
+                f64,
+                "W"
+            ),
+            hr_calc_type: extract_field!(record, "hr_calc_type", String, value_to_string),
+            hr_zone_high_boundary: extract_field!(
+                record,
+                "hr_zone_high_boundary",
+                Vec<f64>,
+                value_to_f64_vec
+            ),
+            max_heart_rate: extract_value_with_unit!(record, "max_heart_rate", f64, f64, "bpm"),
+            power_zone_high_boundary: extract_field!(
+                record,
+                "power_zone_high_boundary",
+                Vec<f64>,
+                value_to_f64_vec
+            ),
+            pwr_calc_type: extract_field!(record, "pwr_calc_type", String, value_to_string),
+            reference_index: extract_field!(record, "reference_index", i64, value_to_i64),
+            reference_mesg: extract_field!(record, "reference_mesg", String, value_to_string),
+            resting_heart_rate: extract_value_with_unit!(
+                record,
+                "resting_heart_rate",
+                f64,
+                f64,
+                "bpm"
+            ),
+            time_in_hr_zone: extract_field!(record, "time_in_hr_zone", Vec<f64>, value_to_f64_vec),
+            time_in_power_zone: extract_field!(
+                record,
+                "time_in_power_zone",
+                Vec<f64>,
+                value_to_f64_vec
+            ),
+            timestamp: extract_field!(record, "timestamp", DateTime<Utc>, to_timestamp),
         }
+    }
+}
 
-        result
-    }};
+#[derive(Serialize, Debug, Clone)]
+pub struct Lap {
+    pub avg_cadence: ValueWithUnit<f64>,
+    pub avg_fractional_cadence: ValueWithUnit<f64>, // Float64
+    pub avg_heart_rate: ValueWithUnit<u8>,
+    pub avg_power: ValueWithUnit<u16>,             // UInt16
+    pub enhanced_avg_speed: ValueWithUnit<f64>,    // Float64
+    pub enhanced_max_altitude: ValueWithUnit<f64>, // Float64
+    pub enhanced_max_speed: ValueWithUnit<f64>,    // Float64
+    pub enhanced_min_altitude: ValueWithUnit<f64>, // Float64
+    pub event: String,
+    pub event_type: String,
+    pub intensity: String,
+    pub max_cadence: ValueWithUnit<f64>,
+    pub max_fractional_cadence: ValueWithUnit<f64>, // Float64
+    pub max_heart_rate: ValueWithUnit<u8>,
+    pub max_power: ValueWithUnit<u16>, // UInt16
+    pub message_index: i64,            // SInt64
+    pub min_heart_rate: ValueWithUnit<u8>,
+    pub sport: String,
+    pub start_time: DateTime<Utc>, // Timestamp
+    pub sub_sport: String,
+    pub timestamp: DateTime<Utc>,               // Timestamp
+    pub total_calories: ValueWithUnit<u16>,     // UInt16
+    pub total_distance: ValueWithUnit<f64>,     // Float64
+    pub total_elapsed_time: ValueWithUnit<f64>, // Float64
+    pub total_timer_time: ValueWithUnit<f64>,   // Float64
+    /// Only set for laps of a structured workout.
+    pub wkt_step_index: Option<i64>,
+}
+
+impl Lap {
+    pub fn from_fitentry(record: &FitDataRecord) -> Self {
+        Lap {
+            avg_cadence: extract_value_with_unit!(record, "avg_cadence", f64, f64, "rpm"),
+            avg_fractional_cadence: extract_value_with_unit!(
+                record,
+                "avg_fractional_cadence",
+                f64,
+                f64,
+                "rpm"
+            ),
+            avg_heart_rate: extract_value_with_unit!(record, "avg_heart_rate", f64, u8, "bpm"),
+            avg_power: extract_value_with_unit!(record, "avg_power", i64, u16, "W"),
+            enhanced_avg_speed: extract_value_with_unit!(
+                record,
+                "enhanced_avg_speed",
+                f64,
+                f64,
+                "m/s"
+            ),
+            enhanced_max_altitude: extract_value_with_unit!(
+                record,
+                "enhanced_max_altitude",
+                f64,
+                f64,
+                "m"
+            ),
+            enhanced_max_speed: extract_value_with_unit!(
+                record,
+                "enhanced_max_speed",
+                f64,
+                f64,
+                "m/s"
+            ),
+            enhanced_min_altitude: extract_value_with_unit!(
+                record,
+                "enhanced_min_altitude",
+                f64,
+                f64,
+                "m"
+            ),
+            event: extract_field!(record, "event", String, value_to_string),
+            event_type: extract_field!(record, "event_type", String, value_to_string),
+            intensity: extract_field!(record, "intensity", String, value_to_string),
+            max_cadence: extract_value_with_unit!(record, "max_cadence", f64, f64, "rpm"),
+            max_fractional_cadence: extract_value_with_unit!(
+                record,
+                "max_fractional_cadence",
+                f64,
+                f64,
+                "rpm"
+            ),
+            max_heart_rate: extract_value_with_unit!(record, "max_heart_rate", i64, u8, "bpm"),
+            max_power: extract_value_with_unit!(record, "max_power", i64, u16, "W"),
+            message_index: extract_field!(record, "message_index", i64, value_to_i64),
+            min_heart_rate: extract_value_with_unit!(record, "min_heart_rate", i64, u8, "bpm"),
+            sport: extract_field!(record, "sport", String, value_to_string),
+            start_time: extract_field!(record, "start_time", DateTime<Utc>, to_timestamp),
+            sub_sport: extract_field!(record, "sub_sport", String, value_to_string),
+            timestamp: extract_field!(record, "timestamp", DateTime<Utc>, to_timestamp),
+            total_calories: extract_value_with_unit!(record, "total_calories", i64, u16, "kcal"),
+            total_distance: extract_value_with_unit!(record, "total_distance", f64, f64, "m"),
+            total_elapsed_time: extract_value_with_unit!(
+                record,
+                "total_elapsed_time",
+                f64,
+                f64,
+                "s"
+            ),
+            total_timer_time: extract_value_with_unit!(record, "total_timer_time", f64, f64, "s"),
+            wkt_step_index: FitEntry::get_field(record, "wkt_step_index").and_then(value_to_i64),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FileId {
+    pub manufacturer: String,
+    pub product_name: String,
+    pub serial_number: u32,          // UInt32z
+    pub time_created: DateTime<Utc>, // Timestamp
+    pub file_type: String,
+}
+
+impl FileId {
+    pub fn from_fitentry(record: &FitDataRecord) -> Self {
+        FileId {
+            manufacturer: extract_field!(record, "manufacturer", String, value_to_string),
+            product_name: extract_field!(record, "product_name", String, value_to_string),
+            serial_number: extract_field!(record, "serial_number", i64, value_to_i64) as u32,
+            time_created: extract_field!(record, "time_created", DateTime<Utc>, to_timestamp),
+            file_type: extract_field!(record, "file_type", String, value_to_string),
+        }
+    }
+
+    /// `manufacturer:serial_number:time_created`, which a device doesn't
+    /// repeat for different recordings. `None` without a creation time or a
+    /// serial number, as files of different devices would collide.
+    pub fn fingerprint(&self) -> Option<String> {
+        (self.time_created.timestamp() != 0 && self.serial_number != 0).then(|| {
+            format!(
+                "{}:{}:{}",
+                self.manufacturer,
+                self.serial_number,
+                self.time_created.timestamp()
+            )
+        })
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeviceInfo {
+    pub descriptor: String,
+    pub device_index: String,
+    pub manufacturer: String,
+    pub product_name: String,
+    pub serial_number: u32, // UInt32z
+    pub source_type: String,
+    pub timestamp: DateTime<Utc>, // Timestamp
+}
+
+impl DeviceInfo {
+    pub fn from_fitentry(record: &FitDataRecord) -> Self {
+        DeviceInfo {
+            descriptor: FitEntry::get_field(record, "descriptor")
+                .and_then(value_to_string)
+                .unwrap_or_else(|| String::from("")),
+            device_index: FitEntry::get_field(record, "device_index")
+                .and_then(value_to_string)
+                .unwrap_or_else(|| String::from("")),
+            manufacturer: FitEntry::get_field(record, "manufacturer")
+                .and_then(value_to_string)
+                .unwrap_or_else(|| String::from("")),
+            product_name: FitEntry::get_field(record, "product_name")
+                .and_then(value_to_string)
+                .unwrap_or_else(|| String::from("")),
+            serial_number: FitEntry::get_field(record, "serial_number")
+                .and_then(value_to_i64)
+                .unwrap_or(0) as u32,
+            source_type: FitEntry::get_field(record, "source_type")
+                .and_then(value_to_string)
+                .unwrap_or_else(|| String::from("")),
+            timestamp: extract_field!(record, "timestamp", DateTime<Utc>, to_timestamp),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Workout {
+    pub capabilities: String,
+    pub num_valid_steps: u16,
+    pub sport: String,
+    pub wkt_name: String,
+}
+
+impl Workout {
+    pub fn from_fitentry(record: &FitDataRecord) -> Self {
+        Workout {
+            capabilities: extract_field!(record, "capabilities", String, value_to_string),
+            num_valid_steps: extract_field!(record, "num_valid_steps", i64, value_to_i64) as u16,
+            sport: extract_field!(record, "sport", String, value_to_string),
+            wkt_name: extract_field!(record, "wkt_name", String, value_to_string),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Event {
+    pub event: String,
+    pub event_group: u8,
+    pub event_type: String,
+    pub timer_trigger: String,
+    pub timestamp: DateTime<Utc>, // Timestamp
+}
+
+impl Event {
+    pub fn from_fitentry(record: &FitDataRecord) -> Self {
+        Event {
+            event: FitEntry::get_field(record, "event")
+                .and_then(value_to_string)
+                .unwrap_or_else(|| String::from("")),
+            event_group: FitEntry::get_field(record, "event_group")
+                .and_then(value_to_i64)
+                .unwrap_or(0) as u8,
+            event_type: FitEntry::get_field(record, "event_type")
+                .and_then(value_to_string)
+                .unwrap_or_else(|| String::from("")),
+            timer_trigger: FitEntry::get_field(record, "timer_trigger")
+                .and_then(value_to_string)
+                .unwrap_or_else(|| String::from("")),
+            timestamp: extract_field!(record, "timestamp", DateTime<Utc>, to_timestamp),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Sport {
+    pub name: String,
+    pub sport: String,
+    pub sub_sport: String,
+}
+
+impl Sport {
+    pub fn from_fitentry(record: &FitDataRecord) -> Self {
+        Sport {
+            name: FitEntry::get_field(record, "name")
+                .and_then(value_to_string)
+                .unwrap_or_else(|| String::from("")),
+            sport: FitEntry::get_field(record, "sport")
+                .and_then(value_to_string)
+                .unwrap_or_else(|| String::from("")),
+            sub_sport: FitEntry::get_field(record, "sub_sport")
+                .and_then(value_to_string)
+                .unwrap_or_else(|| String::from("")),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ZonesTarget {
+    pub functional_threshold_power: ValueWithUnit<f64>,
+    pub pwr_calc_type: String,
+}
+
+impl ZonesTarget {
+    pub fn from_fitentry(record: &FitDataRecord) -> Self {
+        ZonesTarget {
+            functional_threshold_power: extract_value_with_unit!(
+                record,
+                "functional_threshold_power",
+                f64,
+                f64,
+                "W"
+            ),
+            pwr_calc_type: extract_field!(record, "pwr_calc_type", String, value_to_string),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Session {
+    pub avg_cadence: ValueWithUnit<f64>,
+    pub avg_fractional_cadence: ValueWithUnit<f64>, // Float64
+    pub avg_heart_rate: ValueWithUnit<f64>,
+    pub avg_power: ValueWithUnit<f64>,             // UInt16
+    pub avg_temperature: ValueWithUnit<f64>,       // SInt8
+    pub enhanced_avg_altitude: ValueWithUnit<f64>, // Float64
+    pub enhanced_avg_speed: ValueWithUnit<f64>,    // Float64
+    pub enhanced_max_altitude: ValueWithUnit<f64>, // Float64
+    pub enhanced_max_speed: ValueWithUnit<f64>,    // Float64
+    pub enhanced_min_altitude: ValueWithUnit<f64>, // Float64
+    pub event_type: String,
+    pub first_lap_index: ValueWithUnit<f64>, // UInt16
+    pub max_cadence: ValueWithUnit<f64>,
+    pub max_fractional_cadence: ValueWithUnit<f64>, // Float64
+    pub max_heart_rate: ValueWithUnit<f64>,
+    pub max_power: ValueWithUnit<f64>, // UInt16
+    pub message_index: i64,            // SInt64
+    pub min_heart_rate: ValueWithUnit<f64>,
+    pub nec_lat: ValueWithUnit<f64>,  // SInt32
+    pub nec_long: ValueWithUnit<f64>, // SInt32
+    pub num_laps: ValueWithUnit<f64>, // UInt16
+    pub sport: String,
+    pub start_time: DateTime<Utc>, // Timestamp
+    pub sub_sport: String,
+    pub swc_lat: ValueWithUnit<f64>,            // SInt32
+    pub swc_long: ValueWithUnit<f64>,           // SInt32
+    pub threshold_power: ValueWithUnit<f64>,    // UInt16
+    pub timestamp: DateTime<Utc>,               // Timestamp
+    pub total_ascent: ValueWithUnit<f64>,       // UInt16
+    pub total_calories: ValueWithUnit<f64>,     // UInt16
+    pub total_distance: ValueWithUnit<f64>,     // Float64
+    pub total_elapsed_time: ValueWithUnit<f64>, // Float64
+    pub total_timer_time: ValueWithUnit<f64>,   // Float64
+    pub trigger: String,
+}
+
+impl Session {
+    pub fn from_fitentry(record: &FitDataRecord) -> Self {
+        Session {
+            avg_cadence: extract_value_with_unit!(record, "avg_cadence", f64, f64, "rpm"),
+            avg_fractional_cadence: extract_value_with_unit!(
+                record,
+                "avg_fractional_cadence",
+                f64,
+                f64,
+                "rpm"
+            ),
+            avg_heart_rate: extract_value_with_unit!(record, "avg_heart_rate", f64, f64, "bpm"),
+            avg_power: extract_value_with_unit!(record, "avg_power", f64, f64, "W"),
+            avg_temperature: extract_value_with_unit!(record, "avg_temperature", i64, f64, "°C"),
+            enhanced_avg_altitude: extract_value_with_unit!(
+                record,
+                "enhanced_avg_altitude",
+                f64,
+                f64,
+                "m"
+            ),
+            enhanced_avg_speed: extract_value_with_unit!(
+                record,
+                "enhanced_avg_speed",
+                f64,
+                f64,
+                "m/s"
+            ),
+            enhanced_max_altitude: extract_value_with_unit!(
+                record,
+                "enhanced_max_altitude",
+                f64,
+                f64,
+                "m"
+            ),
+            enhanced_max_speed: extract_value_with_unit!(
+                record,
+                "enhanced_max_speed",
+                f64,
+                f64,
+                "m/s"
+            ),
+            enhanced_min_altitude: extract_value_with_unit!(
+                record,
+                "enhanced_min_altitude",
+                f64,
+                f64,
+                "m"
+            ),
+            event_type: extract_field!(record, "event_type", String, value_to_string),
+            first_lap_index: extract_value_with_unit!(record, "first_lap_index", i64, f64, ""),
+            max_cadence: extract_value_with_unit!(record, "max_cadence", f64, f64, "rpm"),
+            max_fractional_cadence: extract_value_with_unit!(
+                record,
+                "max_fractional_cadence",
+                f64,
+                f64,
+                "rpm"
+            ),
+            max_heart_rate: extract_value_with_unit!(record, "max_heart_rate", f64, f64, "bpm"),
+            max_power: extract_value_with_unit!(record, "max_power", f64, f64, "W"),
+            message_index: extract_field!(record, "message_index", i64, value_to_i64),
+            min_heart_rate: extract_value_with_unit!(record, "min_heart_rate", f64, f64, "bpm"),
+            nec_lat: extract_value_with_unit!(record, "nec_lat", i64, f64, "semicircles"),
+            nec_long: extract_value_with_unit!(record, "nec_long", i64, f64, "semicircles"),
+            num_laps: extract_value_with_unit!(record, "num_laps", i64, f64, ""),
+            sport: extract_field!(record, "sport", String, value_to_string),
+            start_time: extract_field!(record, "start_time", DateTime<Utc>, to_timestamp),
+            sub_sport: extract_field!(record, "sub_sport", String, value_to_string),
+            swc_lat: extract_value_with_unit!(record, "swc_lat", i64, f64, "semicircles"),
+            swc_long: extract_value_with_unit!(record, "swc_long", i64, f64, "semicircles"),
+            threshold_power: extract_value_with_unit!(record, "threshold_power", i64, f64, "W"),
+            timestamp: extract_field!(record, "timestamp", DateTime<Utc>, to_timestamp),
+            total_ascent: extract_value_with_unit!(record, "total_ascent", i64, f64, "m"),
+            total_calories: extract_value_with_unit!(record, "total_calories", i64, f64, "kcal"),
+            total_distance: extract_value_with_unit!(record, "total_distance", f64, f64, "m"),
+            total_elapsed_time: extract_value_with_unit!(
+                record,
+                "total_elapsed_time",
+                f64,
+                f64,
+                "s"
+            ),
+            total_timer_time: extract_value_with_unit!(record, "total_timer_time", f64, f64, "s"),
+            trigger: extract_field!(record, "trigger", String, value_to_string),
+        }
+    }
+}
+
+/// A step of the planned workout. Repeat steps use `duration_step` and
+/// `repeat_steps` instead of a duration and target.
+#[derive(Serialize, Debug, Clone)]
+pub struct WorkoutStep {
+    pub duration_time: ValueWithUnit<f64>, // Float64
+    pub duration_type: String,
+    pub intensity: String,
+    pub message_index: i64, // SInt64
+    pub target_type: String,
+    pub target_value: u32,
+    pub wkt_step_name: Option<String>,
+    /// Power targets below 1000 are % of FTP, above it watts offset by 1000.
+    pub custom_target_power_low: Option<f64>,
+    pub custom_target_power_high: Option<f64>,
+    /// First step index of the block a repeat step repeats.
+    pub duration_step: Option<i64>,
+    pub repeat_steps: Option<i64>,
+}
+
+impl WorkoutStep {
+    pub fn from_fitentry(record: &FitDataRecord) -> Self {
+        let optional_f64 = |names: [&str; 2]| {
+            names
+                .iter()
+                .find_map(|name| FitEntry::get_field(record, name).and_then(value_to_f64))
+        };
+        WorkoutStep {
+            duration_time: extract_value_with_unit!(record, "duration_time", f64, f64, ""),
+            duration_type: extract_field!(record, "duration_type", String, value_to_string),
+            intensity: extract_field!(record, "intensity", String, value_to_string),
+            message_index: extract_field!(record, "message_index", i64, value_to_i64),
+            target_type: extract_field!(record, "target_type", String, value_to_string),
+            target_value: extract_field!(record, "target_value", i64, value_to_i64) as u32,
+            wkt_step_name: FitEntry::get_field(record, "wkt_step_name").and_then(value_to_string),
+            custom_target_power_low: optional_f64([
+                "custom_target_power_low",
+                "custom_target_value_low",
+            ]),
+            custom_target_power_high: optional_f64([
+                "custom_target_power_high",
+                "custom_target_value_high",
+            ]),
+            duration_step: FitEntry::get_field(record, "duration_step").and_then(value_to_i64),
+            repeat_steps: FitEntry::get_field(record, "repeat_steps").and_then(value_to_i64),
+        }
+    }
+}
+
+/// The athlete profile the device had configured when recording.
+#[derive(Serialize, Debug, Clone)]
+pub struct FitUserProfile {
+    pub friendly_name: String,
+    pub gender: String,
+    pub age: ValueWithUnit<i64>,
+    pub height: ValueWithUnit<f64>,
+    pub weight: ValueWithUnit<f64>,
+    pub resting_heart_rate: ValueWithUnit<f64>,
+    pub default_max_heart_rate: ValueWithUnit<f64>,
+}
+
+impl FitUserProfile {
+    pub fn from_fitentry(record: &FitDataRecord) -> Self {
+        FitUserProfile {
+            friendly_name: extract_field!(record, "friendly_name", String, value_to_string),
+            gender: extract_field!(record, "gender", String, value_to_string),
+            age: extract_value_with_unit!(record, "age", i64, i64, "years"),
+            height: extract_value_with_unit!(record, "height", f64, f64, "m"),
+            weight: extract_value_with_unit!(record, "weight", f64, f64, "kg"),
+            resting_heart_rate: extract_value_with_unit!(
+                record,
+                "resting_heart_rate",
+                f64,
+                f64,
+                "bpm"
+            ),
+            default_max_heart_rate: extract_value_with_unit!(
+                record,
+                "default_max_heart_rate",
+                f64,
+                f64,
+                "bpm"
+            ),
+        }
+    }
 }
 
 impl FitEntry {
     pub fn get_field<'a>(record: &'a FitDataRecord, field_name: &str) -> Option<&'a FitDataField> {
-        record.fields().into_iter().find(|f| f.name() == field_name)
+        record.fields().iter().find(|f| f.name() == field_name)
     }
 
     pub fn new(record: fitparser::FitDataRecord) -> Self {
         match record.kind() {
-            MesgNum::FileId => FitEntry::FileId {
-                manufacturer: extract_field!(&record, "manufacturer", String, value_to_string),
-                product_name: extract_field!(&record, "product_name", String, value_to_string),
-                serial_number: extract_field!(&record, "serial_number", i64, value_to_i64) as u32,
-                time_created: extract_field!(&record, "time_created", DateTime<Utc>, to_timestamp),
-                file_type: extract_field!(&record, "file_type", String, value_to_string),
-            },
+            MesgNum::FileId => FitEntry::FileId(FileId::from_fitentry(&record)),
             MesgNum::FileCreator => FitEntry::FileCreator {
                 software_version: extract_field!(&record, "software_version", i64, value_to_i64)
                     as u16,
             },
-            MesgNum::DeviceInfo => FitEntry::DeviceInfo {
-                descriptor: FitEntry::get_field(&record, "descriptor")
-                    .and_then(value_to_string)
-                    .unwrap_or_else(|| String::from("")),
-                device_index: FitEntry::get_field(&record, "device_index")
-                    .and_then(value_to_string)
-                    .unwrap_or_else(|| String::from("")),
-                manufacturer: FitEntry::get_field(&record, "manufacturer")
-                    .and_then(value_to_string)
-                    .unwrap_or_else(|| String::from("")),
-                product_name: FitEntry::get_field(&record, "product_name")
-                    .and_then(value_to_string)
-                    .unwrap_or_else(|| String::from("")),
-                serial_number: FitEntry::get_field(&record, "serial_number")
-                    .and_then(value_to_i64)
-                    .unwrap_or_else(|| 0) as u32,
-                source_type: FitEntry::get_field(&record, "source_type")
-                    .and_then(value_to_string)
-                    .unwrap_or_else(|| String::from("")),
-                timestamp: FitEntry::get_field(&record, "timestamp")
-                    .and_then(to_timestamp)
-                    .unwrap_or_else(|| Utc::now()),
-            },
+            MesgNum::DeviceInfo => FitEntry::DeviceInfo(DeviceInfo::from_fitentry(&record)),
             MesgNum::DeveloperDataId => FitEntry::DeveloperDataId {
                 application_id: FitEntry::get_field(&record, "application_id")
                     .and_then(value_to_string)
@@ -479,21 +1023,21 @@ impl FitEntry {
                     .into_bytes(),
                 application_version: FitEntry::get_field(&record, "application_version")
                     .and_then(value_to_i64)
-                    .unwrap_or_else(|| 0) as u32,
+                    .unwrap_or(0) as u32,
                 developer_data_index: FitEntry::get_field(&record, "developer_data_index")
                     .and_then(value_to_i64)
-                    .unwrap_or_else(|| 0) as u8,
+                    .unwrap_or(0) as u8,
             },
             MesgNum::FieldDescription => FitEntry::FieldDescription {
                 array: FitEntry::get_field(&record, "array")
                     .and_then(value_to_i64)
-                    .unwrap_or_else(|| 0) as u8,
+                    .unwrap_or(0) as u8,
                 developer_data_index: FitEntry::get_field(&record, "developer_data_index")
                     .and_then(value_to_i64)
-                    .unwrap_or_else(|| 0) as u8,
+                    .unwrap_or(0) as u8,
                 field_definition_number: FitEntry::get_field(&record, "field_definition_number")
                     .and_then(value_to_i64)
-                    .unwrap_or_else(|| 0) as u8,
+                    .unwrap_or(0) as u8,
                 field_name: FitEntry::get_field(&record, "field_name")
                     .and_then(value_to_string)
                     .unwrap_or_else(|| String::from("")),
@@ -501,142 +1045,16 @@ impl FitEntry {
                     .and_then(value_to_string)
                     .unwrap_or_else(|| String::from("")),
             },
-            MesgNum::Workout => FitEntry::Workout {
-                capabilities: extract_field!(&record, "capabilities", String, value_to_string),
-                num_valid_steps: extract_field!(&record, "num_valid_steps", i64, value_to_i64)
-                    as u16,
-                sport: extract_field!(&record, "sport", String, value_to_string),
-                wkt_name: extract_field!(&record, "wkt_name", String, value_to_string),
-            },
-            MesgNum::WorkoutStep => FitEntry::WorkoutStep {
-                duration_time: extract_value_with_unit!(&record, "duration_time", f64, f64, ""),
-                duration_type: extract_field!(&record, "duration_type", String, value_to_string),
-                intensity: extract_field!(&record, "intensity", String, value_to_string),
-                message_index: extract_field!(&record, "message_index", i64, value_to_i64),
-                target_type: extract_field!(&record, "target_type", String, value_to_string),
-                target_value: extract_field!(&record, "target_value", i64, value_to_i64) as u32,
-            },
-            MesgNum::Event => FitEntry::Event {
-                event: FitEntry::get_field(&record, "event")
-                    .and_then(value_to_string)
-                    .unwrap_or_else(|| String::from("")),
-                event_group: FitEntry::get_field(&record, "event_group")
-                    .and_then(value_to_i64)
-                    .unwrap_or_else(|| 0) as u8,
-                event_type: FitEntry::get_field(&record, "event_type")
-                    .and_then(value_to_string)
-                    .unwrap_or_else(|| String::from("")),
-                timer_trigger: FitEntry::get_field(&record, "timer_trigger")
-                    .and_then(value_to_string)
-                    .unwrap_or_else(|| String::from("")),
-                timestamp: FitEntry::get_field(&record, "timestamp")
-                    .and_then(to_timestamp)
-                    .unwrap_or_else(|| Utc::now()),
-            },
-            MesgNum::Sport => FitEntry::Sport {
-                name: FitEntry::get_field(&record, "name")
-                    .and_then(value_to_string)
-                    .unwrap_or_else(|| String::from("")),
-                sport: FitEntry::get_field(&record, "sport")
-                    .and_then(value_to_string)
-                    .unwrap_or_else(|| String::from("")),
-                sub_sport: FitEntry::get_field(&record, "sub_sport")
-                    .and_then(value_to_string)
-                    .unwrap_or_else(|| String::from("")),
-            },
-            MesgNum::ZonesTarget => FitEntry::ZonesTarget {
-                functional_threshold_power: extract_value_with_unit!(
-                    &record,
-                    "functional_threshold_power",
-                    f64,
-                    f64,
-                    "W"
-                ),
-                pwr_calc_type: extract_field!(&record, "pwr_calc_type", String, value_to_string),
-            },
-            MesgNum::Record => FitEntry::Record(Record::from_fitentry(&record)),
-            MesgNum::Lap => FitEntry::Lap {
-                avg_cadence: extract_value_with_unit!(&record, "avg_cadence", f64, f64, "rpm"),
-                avg_fractional_cadence: extract_value_with_unit!(
-                    &record,
-                    "avg_fractional_cadence",
-                    f64,
-                    f64,
-                    "rpm"
-                ),
-                avg_heart_rate: extract_value_with_unit!(&record, "avg_heart_rate", f64, u8, "bpm"),
-                avg_power: extract_value_with_unit!(&record, "avg_power", i64, u16, "W"),
-                enhanced_avg_speed: extract_value_with_unit!(
-                    &record,
-                    "enhanced_avg_speed",
-                    f64,
-                    f64,
-                    "m/s"
-                ),
-                enhanced_max_altitude: extract_value_with_unit!(
-                    &record,
-                    "enhanced_max_altitude",
-                    f64,
-                    f64,
-                    "m"
-                ),
-                enhanced_max_speed: extract_value_with_unit!(
-                    &record,
-                    "enhanced_max_speed",
-                    f64,
-                    f64,
-                    "m/s"
-                ),
-                enhanced_min_altitude: extract_value_with_unit!(
-                    &record,
-                    "enhanced_min_altitude",
-                    f64,
-                    f64,
-                    "m"
-                ),
-                event: extract_field!(&record, "event", String, value_to_string),
-                event_type: extract_field!(&record, "event_type", String, value_to_string),
-                intensity: extract_field!(&record, "intensity", String, value_to_string),
-                max_cadence: extract_value_with_unit!(&record, "max_cadence", f64, f64, "rpm"),
-                max_fractional_cadence: extract_value_with_unit!(
-                    &record,
-                    "max_fractional_cadence",
-                    f64,
-                    f64,
-                    "rpm"
-                ),
-                max_heart_rate: extract_value_with_unit!(&record, "max_heart_rate", i64, u8, "bpm"),
-                max_power: extract_value_with_unit!(&record, "max_power", i64, u16, "W"),
-                message_index: extract_field!(&record, "message_index", i64, value_to_i64),
-                min_heart_rate: extract_value_with_unit!(&record, "min_heart_rate", i64, u8, "bpm"),
-                sport: extract_field!(&record, "sport", String, value_to_string),
-                start_time: extract_field!(&record, "start_time", DateTime<Utc>, to_timestamp),
-                sub_sport: extract_field!(&record, "sub_sport", String, value_to_string),
-                timestamp: extract_field!(&record, "timestamp", DateTime<Utc>, to_timestamp),
-                total_calories: extract_value_with_unit!(
-                    &record,
-                    "total_calories",
-                    i64,
-                    u16,
-                    "kcal"
-                ),
-                total_distance: extract_value_with_unit!(&record, "total_distance", f64, f64, "m"),
-                total_elapsed_time: extract_value_with_unit!(
-                    &record,
-                    "total_elapsed_time",
-                    f64,
-                    f64,
-                    "s"
-                ),
-                total_timer_time: extract_value_with_unit!(
-                    &record,
-                    "total_timer_time",
-                    f64,
-                    f64,
-                    "s"
-                ),
-                wkt_step_index: extract_field!(&record, "wkt_step_index", i64, value_to_i64),
+            MesgNum::Workout => FitEntry::Workout(Workout::from_fitentry(&record)),
+            MesgNum::WorkoutStep => FitEntry::WorkoutStep(WorkoutStep::from_fitentry(&record)),
+            MesgNum::Event => FitEntry::Event(Event::from_fitentry(&record)),
+            MesgNum::Sport => FitEntry::Sport(Sport::from_fitentry(&record)),
+            MesgNum::ZonesTarget => FitEntry::ZonesTarget(ZonesTarget::from_fitentry(&record)),
+            MesgNum::Record => match Record::from_fitentry(&record) {
+                Some(record) => FitEntry::Record(record),
+                None => FitEntry::Other,
             },
+            MesgNum::Lap => FitEntry::Lap(Lap::from_fitentry(&record)),
             MesgNum::Activity => FitEntry::Activity {
                 event: extract_field!(&record, "event", String, value_to_string),
                 event_type: extract_field!(&record, "event_type", String, value_to_string),
@@ -657,132 +1075,7 @@ impl FitEntry {
                 ),
                 type_: extract_field!(&record, "type", String, value_to_string),
             },
-            MesgNum::Session => FitEntry::Session {
-                avg_cadence: extract_value_with_unit!(&record, "avg_cadence", f64, f64, "rpm"),
-                avg_fractional_cadence: extract_value_with_unit!(
-                    &record,
-                    "avg_fractional_cadence",
-                    f64,
-                    f64,
-                    "rpm"
-                ),
-                avg_heart_rate: extract_value_with_unit!(
-                    &record,
-                    "avg_heart_rate",
-                    f64,
-                    f64,
-                    "bpm"
-                ),
-                avg_power: extract_value_with_unit!(&record, "avg_power", f64, f64, "W"),
-                avg_temperature: extract_value_with_unit!(
-                    &record,
-                    "avg_temperature",
-                    i64,
-                    f64,
-                    "°C"
-                ),
-                enhanced_avg_altitude: extract_value_with_unit!(
-                    &record,
-                    "enhanced_avg_altitude",
-                    f64,
-                    f64,
-                    "m"
-                ),
-                enhanced_avg_speed: extract_value_with_unit!(
-                    &record,
-                    "enhanced_avg_speed",
-                    f64,
-                    f64,
-                    "m/s"
-                ),
-                enhanced_max_altitude: extract_value_with_unit!(
-                    &record,
-                    "enhanced_max_altitude",
-                    f64,
-                    f64,
-                    "m"
-                ),
-                enhanced_max_speed: extract_value_with_unit!(
-                    &record,
-                    "enhanced_max_speed",
-                    f64,
-                    f64,
-                    "m/s"
-                ),
-                enhanced_min_altitude: extract_value_with_unit!(
-                    &record,
-                    "enhanced_min_altitude",
-                    f64,
-                    f64,
-                    "m"
-                ),
-                event_type: extract_field!(&record, "event_type", String, value_to_string),
-                first_lap_index: extract_value_with_unit!(&record, "first_lap_index", i64, f64, ""),
-                max_cadence: extract_value_with_unit!(&record, "max_cadence", f64, f64, "rpm"),
-                max_fractional_cadence: extract_value_with_unit!(
-                    &record,
-                    "max_fractional_cadence",
-                    f64,
-                    f64,
-                    "rpm"
-                ),
-                max_heart_rate: extract_value_with_unit!(
-                    &record,
-                    "max_heart_rate",
-                    f64,
-                    f64,
-                    "bpm"
-                ),
-                max_power: extract_value_with_unit!(&record, "max_power", f64, f64, "W"),
-                message_index: extract_field!(&record, "message_index", i64, value_to_i64),
-                min_heart_rate: extract_value_with_unit!(
-                    &record,
-                    "min_heart_rate",
-                    f64,
-                    f64,
-                    "bpm"
-                ),
-                nec_lat: extract_value_with_unit!(&record, "nec_lat", i64, f64, "semicircles"),
-                nec_long: extract_value_with_unit!(&record, "nec_long", i64, f64, "semicircles"),
-                num_laps: extract_value_with_unit!(&record, "num_laps", i64, f64, ""),
-                sport: extract_field!(&record, "sport", String, value_to_string),
-                start_time: extract_field!(&record, "start_time", DateTime<Utc>, to_timestamp),
-                sub_sport: extract_field!(&record, "sub_sport", String, value_to_string),
-                swc_lat: extract_value_with_unit!(&record, "swc_lat", i64, f64, "semicircles"),
-                swc_long: extract_value_with_unit!(&record, "swc_long", i64, f64, "semicircles"),
-                threshold_power: extract_value_with_unit!(
-                    &record,
-                    "threshold_power",
-                    i64,
-                    f64,
-                    "W"
-                ),
-                timestamp: extract_field!(&record, "timestamp", DateTime<Utc>, to_timestamp),
-                total_ascent: extract_value_with_unit!(&record, "total_ascent", i64, f64, "m"),
-                total_calories: extract_value_with_unit!(
-                    &record,
-                    "total_calories",
-                    i64,
-                    f64,
-                    "kcal"
-                ),
-                total_distance: extract_value_with_unit!(&record, "total_distance", f64, f64, "m"),
-                total_elapsed_time: extract_value_with_unit!(
-                    &record,
-                    "total_elapsed_time",
-                    f64,
-                    f64,
-                    "s"
-                ),
-                total_timer_time: extract_value_with_unit!(
-                    &record,
-                    "total_timer_time",
-                    f64,
-                    f64,
-                    "s"
-                ),
-                trigger: extract_field!(&record, "trigger", String, value_to_string),
-            },
+            MesgNum::Session => FitEntry::Session(Session::from_fitentry(&record)),
             // TODO: this is useful
             MesgNum::Set => FitEntry::Other,
             MesgNum::StressLevel => FitEntry::Other,
@@ -801,10 +1094,10 @@ impl FitEntry {
             // MesgNum::Split => FitEntry::Split {
             //     start_time: FitEntry::get_field(&record, "start_time")
             //         .and_then(to_timestamp)
-            //         .unwrap_or_else(|| Utc::now()),
+            //         .unwrap_or_else(Utc::now),
             //     end_time: FitEntry::get_field(&record, "end_time")
             //         .and_then(to_timestamp)
-            //         .unwrap_or_else(|| Utc::now()),
+            //         .unwrap_or_else(Utc::now),
             //     name: FitEntry::get_field(&record, "name").and_then(value_to_string),
             // },
             MesgNum::ClimbPro => FitEntry::Other,
@@ -820,7 +1113,7 @@ impl FitEntry {
             MesgNum::Value(_) => FitEntry::Other,
             MesgNum::Capabilities => FitEntry::Other,
             MesgNum::DeviceSettings => FitEntry::Other,
-            MesgNum::UserProfile => FitEntry::Other,
+            MesgNum::UserProfile => FitEntry::UserProfile(FitUserProfile::from_fitentry(&record)),
             MesgNum::HrmProfile => FitEntry::Other,
             MesgNum::SdmProfile => FitEntry::Other,
             MesgNum::BikeProfile => FitEntry::Other,
@@ -828,13 +1121,11 @@ impl FitEntry {
             MesgNum::PowerZone => FitEntry::Other,
             MesgNum::MetZone => FitEntry::Other,
             MesgNum::Goal => FitEntry::Other,
-            MesgNum::Session => FitEntry::Other,
             MesgNum::Schedule => FitEntry::Other,
             MesgNum::WeightScale => FitEntry::Other,
             MesgNum::Course => FitEntry::Other,
             MesgNum::CoursePoint => FitEntry::Other,
             MesgNum::Totals => FitEntry::Other,
-            MesgNum::Activity => FitEntry::Other,
             MesgNum::Software => FitEntry::Other,
             MesgNum::FileCapabilities => FitEntry::Other,
             MesgNum::MesgCapabilities => FitEntry::Other,
@@ -886,7 +1177,209 @@ impl FitEntry {
             MesgNum::BarometerData => FitEntry::Other,
             MesgNum::OneDSensorCalibration => FitEntry::Other,
             MesgNum::MonitoringHrData => FitEntry::Other,
-            MesgNum::TimeInZone => FitEntry::Other,
+            MesgNum::TimeInZone => FitEntry::TimeInZone(TimeInZone::from_fitentry(&record)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fit_record as record, fit_time as at, text};
+
+    #[test]
+    fn value_to_units_rejects_mistyped_values() {
+        let field = FitDataField::new("power".to_owned(), 7, text("high"), "W".to_owned());
+        assert!(value_to_units(&field).is_none());
+        let field = FitDataField::new("power".to_owned(), 7, Value::UInt16(250), "W".to_owned());
+        assert_eq!(value_to_units(&field).map(|v| v.value), Some(250.0));
+    }
+
+    /// Every typed message falls back to its defaults for fields of the
+    /// wrong FIT type instead of panicking or misreading them.
+    #[test]
+    fn messages_tolerate_mistyped_fields() {
+        type Check = fn(&FitDataRecord) -> bool;
+        let cases: Vec<(FitDataRecord, Check)> = vec![
+            (
+                record(
+                    MesgNum::TimeInZone,
+                    vec![
+                        ("functional_threshold_power", text("250")),
+                        ("max_heart_rate", Value::Array(vec![Value::UInt8(190)])),
+                        ("hr_zone_high_boundary", text("120,140")),
+                        ("reference_index", Value::Float64(1.5)),
+                        ("timestamp", Value::UInt32(0)),
+                    ],
+                ),
+                |record| {
+                    let time_in_zone = TimeInZone::from_fitentry(record);
+                    time_in_zone.functional_threshold_power.value == 0.0
+                        && time_in_zone.max_heart_rate.value == 0.0
+                        && time_in_zone.hr_zone_high_boundary.is_empty()
+                },
+            ),
+            (
+                record(
+                    MesgNum::UserProfile,
+                    vec![
+                        ("weight", text("heavy")),
+                        ("age", Value::Array(vec![])),
+                        ("resting_heart_rate", Value::Timestamp(Default::default())),
+                        ("friendly_name", Value::UInt8(3)),
+                    ],
+                ),
+                |record| {
+                    let profile = FitUserProfile::from_fitentry(record);
+                    profile.weight.value == 0.0
+                        && profile.age.value == 0
+                        && profile.resting_heart_rate.value == 0.0
+                        && profile.friendly_name.is_empty()
+                },
+            ),
+            (
+                record(
+                    MesgNum::Lap,
+                    vec![
+                        ("avg_power", text("fast")),
+                        ("max_heart_rate", Value::Float64(f64::NAN)),
+                        ("start_time", Value::UInt32(1)),
+                        ("wkt_step_index", text("1")),
+                        ("total_timer_time", Value::Array(vec![Value::Float64(1.0)])),
+                    ],
+                ),
+                |record| {
+                    let lap = Lap::from_fitentry(record);
+                    lap.avg_power.value == 0
+                        && lap.start_time.timestamp() == 0
+                        && lap.wkt_step_index.is_none()
+                        && lap.total_timer_time.value == 0.0
+                },
+            ),
+            (
+                record(
+                    MesgNum::WorkoutStep,
+                    vec![
+                        ("duration_time", text("5 min")),
+                        ("duration_type", Value::UInt8(0)),
+                        ("custom_target_power_low", text("low")),
+                        ("custom_target_value_low", Value::UInt32(1250)),
+                        ("repeat_steps", Value::Array(vec![Value::UInt32(3)])),
+                        ("wkt_step_name", Value::Float32(1.0)),
+                    ],
+                ),
+                |record| {
+                    let step = WorkoutStep::from_fitentry(record);
+                    step.duration_time.value == 0.0
+                        && step.duration_type.is_empty()
+                        && step.custom_target_power_low == Some(1250.0)
+                        && step.repeat_steps.is_none()
+                        && step.wkt_step_name.is_none()
+                },
+            ),
+        ];
+        for (record, defaults) in cases {
+            assert!(defaults(&record), "{:?}", record.kind());
+        }
+    }
+
+    #[test]
+    fn activity_collects_typed_messages() {
+        let records = vec![
+            record(
+                MesgNum::FileId,
+                vec![
+                    ("manufacturer", text("garmin")),
+                    ("serial_number", Value::UInt32z(1234)),
+                    ("time_created", at(1_700_000_000)),
+                ],
+            ),
+            record(MesgNum::Sport, vec![("sport", text("cycling"))]),
+            record(
+                MesgNum::Record,
+                vec![
+                    ("timestamp", at(1_700_000_000)),
+                    ("power", Value::UInt16(200)),
+                ],
+            ),
+            record(
+                MesgNum::Record,
+                vec![
+                    ("timestamp", at(1_700_000_001)),
+                    ("power", Value::UInt16(210)),
+                ],
+            ),
+            record(MesgNum::Lap, vec![("message_index", Value::UInt16(0))]),
+            record(MesgNum::WorkoutStep, vec![("duration_type", text("time"))]),
+            record(MesgNum::Hrv, vec![("time", Value::Float64(0.8))]),
+        ];
+        let activity = Activity::from_records(records);
+        let file_id = activity.file_id.unwrap();
+        assert_eq!(file_id.manufacturer, "garmin");
+        assert_eq!(file_id.serial_number, 1234);
+        assert!(activity.sport.is_some());
+        assert_eq!(activity.records.len(), 2);
+        assert_eq!(activity.records[1].power.value, 210);
+        assert_eq!(activity.laps.len(), 1);
+        assert_eq!(activity.workout_steps.len(), 1);
+        assert!(activity.sessions.is_empty());
+    }
+
+    #[test]
+    fn records_without_a_timestamp_are_skipped() {
+        let untimed = record(MesgNum::Record, vec![("power", Value::UInt16(200))]);
+        assert!(Record::from_fitentry(&untimed).is_none());
+        let activity = Activity::from_records(vec![untimed]);
+        assert!(activity.records.is_empty());
+    }
+
+    #[test]
+    fn record_speed_falls_back_to_speed() {
+        let legacy = record(
+            MesgNum::Record,
+            vec![
+                ("timestamp", at(1_700_000_000)),
+                ("speed", Value::Float64(8.5)),
+            ],
+        );
+        assert_eq!(
+            Record::from_fitentry(&legacy).unwrap().enhanced_speed.value,
+            8.5
+        );
+        let enhanced = record(
+            MesgNum::Record,
+            vec![
+                ("timestamp", at(1_700_000_000)),
+                ("speed", Value::Float64(8.5)),
+                ("enhanced_speed", Value::Float64(9.25)),
+            ],
+        );
+        assert_eq!(
+            Record::from_fitentry(&enhanced)
+                .unwrap()
+                .enhanced_speed
+                .value,
+            9.25
+        );
+    }
+
+    #[test]
+    fn fingerprint_needs_creation_time_and_serial_number() {
+        let file_id = |serial_number: u32, time_created: i64| {
+            FileId::from_fitentry(&record(
+                MesgNum::FileId,
+                vec![
+                    ("manufacturer", text("garmin")),
+                    ("serial_number", Value::UInt32z(serial_number)),
+                    ("time_created", at(time_created)),
+                ],
+            ))
+        };
+        assert_eq!(
+            file_id(1234, 1_700_000_000).fingerprint().as_deref(),
+            Some("garmin:1234:1700000000")
+        );
+        assert_eq!(file_id(0, 1_700_000_000).fingerprint(), None);
+        assert_eq!(file_id(1234, 0).fingerprint(), None);
+    }
+}
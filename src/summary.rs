@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use fitparser::profile::MesgNum;
+use serde::{Deserialize, Serialize};
+
+use crate::structures::{FitDataMap, ValueWithUnitsName};
+
+type FitFields = BTreeMap<String, ValueWithUnitsName>;
+
+/// Compact per-activity overview used for listings, so clients don't have to
+/// pull the whole `fit_data` map to render an activity row.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ActivitySummary {
+    pub start_time: Option<DateTime<Utc>>,
+    pub sport: Option<String>,
+    pub sub_sport: Option<String>,
+    pub duration: Option<f64>, // seconds of timer time
+    pub distance: Option<f64>, // meters
+    pub avg_power: Option<f64>,
+    pub max_power: Option<f64>,
+    pub avg_heart_rate: Option<f64>,
+}
+
+fn first<'a>(data: &'a FitDataMap, kind: &MesgNum) -> Option<&'a FitFields> {
+    data.get(kind).and_then(|entries| entries.first())
+}
+
+fn get_f64(fields: Option<&FitFields>, name: &str) -> Option<f64> {
+    fields.and_then(|f| f.get(name)).and_then(|v| v.as_f64())
+}
+
+fn get_string(fields: Option<&FitFields>, name: &str) -> Option<String> {
+    fields.and_then(|f| f.get(name)).and_then(|v| v.as_string())
+}
+
+impl ActivitySummary {
+    pub fn from_fit_data(data: &FitDataMap) -> Self {
+        let session = first(data, &MesgNum::Session);
+        let sport = first(data, &MesgNum::Sport);
+        let file_id = first(data, &MesgNum::FileId);
+
+        ActivitySummary {
+            start_time: session
+                .and_then(|s| s.get("start_time"))
+                .or_else(|| file_id.and_then(|f| f.get("time_created")))
+                .and_then(|v| v.as_timestamp()),
+            sport: get_string(sport, "sport").or_else(|| get_string(session, "sport")),
+            sub_sport: get_string(sport, "sub_sport").or_else(|| get_string(session, "sub_sport")),
+            duration: get_f64(session, "total_timer_time"),
+            distance: get_f64(session, "total_distance"),
+            avg_power: get_f64(session, "avg_power"),
+            max_power: get_f64(session, "max_power"),
+            avg_heart_rate: get_f64(session, "avg_heart_rate"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fitparser::Value;
+
+    use super::*;
+    use crate::test_support::{fit_data, fit_record, fit_time, text};
+
+    fn file_id() -> fitparser::FitDataRecord {
+        fit_record(
+            MesgNum::FileId,
+            vec![("time_created", fit_time(1_700_000_000))],
+        )
+    }
+
+    fn session(sport: &str) -> fitparser::FitDataRecord {
+        fit_record(
+            MesgNum::Session,
+            vec![
+                ("start_time", fit_time(1_700_000_060)),
+                ("sport", text(sport)),
+                ("sub_sport", text("road")),
+                ("total_timer_time", Value::Float64(3600.0)),
+                ("total_distance", Value::Float64(30_000.0)),
+                ("avg_power", Value::UInt16(210)),
+                ("max_power", Value::UInt16(850)),
+                ("avg_heart_rate", Value::UInt8(145)),
+            ],
+        )
+    }
+
+    #[test]
+    fn summarises_the_session() {
+        let summary =
+            ActivitySummary::from_fit_data(&fit_data(vec![file_id(), session("cycling")]));
+        assert_eq!(summary.start_time.unwrap().timestamp(), 1_700_000_060);
+        assert_eq!(summary.sport.as_deref(), Some("cycling"));
+        assert_eq!(summary.sub_sport.as_deref(), Some("road"));
+        assert_eq!(summary.duration, Some(3600.0));
+        assert_eq!(summary.distance, Some(30_000.0));
+        assert_eq!(summary.avg_power, Some(210.0));
+        assert_eq!(summary.max_power, Some(850.0));
+        assert_eq!(summary.avg_heart_rate, Some(145.0));
+    }
+
+    #[test]
+    fn falls_back_to_the_file_creation_time() {
+        let summary = ActivitySummary::from_fit_data(&fit_data(vec![file_id()]));
+        assert_eq!(summary.start_time.unwrap().timestamp(), 1_700_000_000);
+        assert_eq!(summary.sport, None);
+        assert_eq!(summary.duration, None);
+    }
+
+    #[test]
+    fn sport_message_wins_over_the_session() {
+        let sport = fit_record(
+            MesgNum::Sport,
+            vec![("sport", text("running")), ("sub_sport", text("trail"))],
+        );
+        let summary = ActivitySummary::from_fit_data(&fit_data(vec![sport, session("cycling")]));
+        assert_eq!(summary.sport.as_deref(), Some("running"));
+        assert_eq!(summary.sub_sport.as_deref(), Some("trail"));
+
+        // a Sport message without the field leaves it to the session
+        let sport = fit_record(MesgNum::Sport, vec![("name", text("Ride"))]);
+        let summary = ActivitySummary::from_fit_data(&fit_data(vec![sport, session("cycling")]));
+        assert_eq!(summary.sport.as_deref(), Some("cycling"));
+    }
+
+    #[test]
+    fn empty_data_has_an_empty_summary() {
+        let summary = ActivitySummary::from_fit_data(&FitDataMap::new());
+        assert_eq!(summary.start_time, None);
+        assert_eq!(summary.sport, None);
+    }
+}
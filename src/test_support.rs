@@ -0,0 +1,45 @@
+//! Builders shared by the unit tests.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Local, Utc};
+use fitparser::{profile::MesgNum, FitDataField, FitDataRecord, Value};
+
+use crate::structures::{merge_by_kind, FitDataMap};
+
+/// A FIT message of `kind` with `fields` in order and without units.
+pub fn fit_record(kind: MesgNum, fields: Vec<(&str, Value)>) -> FitDataRecord {
+    let mut record = FitDataRecord::new(kind);
+    for (number, (name, value)) in fields.into_iter().enumerate() {
+        record.push(FitDataField::new(
+            name.to_owned(),
+            number as u8,
+            value,
+            String::new(),
+        ));
+    }
+    record
+}
+
+/// The untyped message map of `records`, as the analysis reads it.
+pub fn fit_data(records: Vec<FitDataRecord>) -> FitDataMap {
+    records.into_iter().fold(BTreeMap::new(), merge_by_kind)
+}
+
+pub fn text(value: &str) -> Value {
+    Value::String(value.to_owned())
+}
+
+/// A FIT timestamp value at the unix `timestamp`.
+pub fn fit_time(timestamp: i64) -> Value {
+    Value::Timestamp(
+        DateTime::<Utc>::from_timestamp(timestamp, 0)
+            .unwrap()
+            .with_timezone(&Local),
+    )
+}
+
+/// `(timestamp, watts)` samples at 1 Hz for `seconds` from `from`.
+pub fn steady(from: i64, seconds: i64, watts: f64) -> Vec<(i64, f64)> {
+    (from..from + seconds).map(|t| (t, watts)).collect()
+}
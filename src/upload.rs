@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use axum::body::Bytes;
+use fitparser::from_reader;
+use flate2::read::MultiGzDecoder;
+use sha2::{Digest, Sha256};
+use tokio::{
+    sync::{Mutex as AsyncMutex, OwnedMutexGuard, Semaphore},
+    task::JoinSet,
+};
+use zip::ZipArchive;
+
+use crate::analysis::{analyse_activity, AnalysisOptions};
+use crate::error::AppError;
+use crate::structures::MongoSchema;
+use crate::users::UserProfile;
+
+/// Largest request body accepted by the upload endpoint.
+pub const MAX_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+/// Largest FIT file unpacked from an archive or decompressed.
+pub const MAX_FIT_FILE_BYTES: u64 = 64 * 1024 * 1024;
+/// How many bytes all files of one upload may unpack or decompress to
+/// together, which keeps zip bombs from exhausting memory.
+pub const MAX_UNPACKED_BYTES: u64 = 256 * 1024 * 1024;
+/// How many files and archive entries one upload may have.
+pub const MAX_UPLOAD_ENTRIES: usize = 1000;
+/// How many files of one upload are parsed and analysed at the same time.
+const MAX_CONCURRENT_ANALYSES: usize = 4;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+
+/// Serialises the storing of uploads per user, so concurrent uploads don't
+/// judge personal records against the same history and both claim them.
+/// A user's entry only lives while someone holds or waits for its lock.
+#[derive(Clone, Default)]
+pub struct UserLocks(Arc<Mutex<HashMap<String, Arc<AsyncMutex<()>>>>>);
+
+impl UserLocks {
+    pub async fn lock(&self, user_id: &str) -> UserLock {
+        let lock = self
+            .0
+            .lock()
+            .expect("user locks are never poisoned")
+            .entry(user_id.to_owned())
+            .or_default()
+            .clone();
+        UserLock {
+            guard: Some(lock.lock_owned().await),
+            user_id: user_id.to_owned(),
+            locks: self.clone(),
+        }
+    }
+}
+
+/// Held lock of one user, removing the user's entry once nobody else
+/// holds or waits for it.
+pub struct UserLock {
+    guard: Option<OwnedMutexGuard<()>>,
+    user_id: String,
+    locks: UserLocks,
+}
+
+impl Drop for UserLock {
+    fn drop(&mut self) {
+        let mut locks = self.locks.0.lock().expect("user locks are never poisoned");
+        self.guard.take();
+        // the map's own reference is the last one
+        if locks
+            .get(&self.user_id)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(&self.user_id);
+        }
+    }
+}
+
+/// One file of a multipart upload, or an entry unpacked from a ZIP archive,
+/// after gzip decompression.
+#[derive(Debug)]
+pub struct UploadedFile {
+    pub name: String,
+    pub bytes: Bytes,
+}
+
+/// What is left of the bytes and entries one upload may unpack to.
+#[derive(Debug)]
+pub struct UnpackBudget {
+    bytes: u64,
+    entries: usize,
+}
+
+impl Default for UnpackBudget {
+    fn default() -> Self {
+        UnpackBudget {
+            bytes: MAX_UNPACKED_BYTES,
+            entries: MAX_UPLOAD_ENTRIES,
+        }
+    }
+}
+
+impl UnpackBudget {
+    fn take_entries(&mut self, name: &str, count: usize) -> Result<(), AppError> {
+        self.entries = self.entries.checked_sub(count).ok_or_else(|| {
+            AppError::Validation(format!(
+                "{name} exceeds the limit of {MAX_UPLOAD_ENTRIES} files per upload"
+            ))
+        })?;
+        Ok(())
+    }
+
+    /// Reads `reader` to the end, failing once it yields more than
+    /// `MAX_FIT_FILE_BYTES` or more than is left of the budget.
+    fn read(&mut self, name: &str, reader: impl Read) -> Result<Vec<u8>, ReadError> {
+        let limit = self.bytes.min(MAX_FIT_FILE_BYTES);
+        let mut bytes = Vec::new();
+        reader
+            .take(limit + 1)
+            .read_to_end(&mut bytes)
+            .map_err(ReadError::Io)?;
+        if bytes.len() as u64 > limit {
+            let message = if limit == MAX_FIT_FILE_BYTES {
+                format!("{name} unpacks to more than {MAX_FIT_FILE_BYTES} bytes")
+            } else {
+                format!(
+                    "{name} exceeds the limit of {MAX_UNPACKED_BYTES} unpacked bytes per upload"
+                )
+            };
+            return Err(ReadError::TooLarge(AppError::Validation(message)));
+        }
+        self.bytes -= bytes.len() as u64;
+        Ok(bytes)
+    }
+}
+
+/// Why `UnpackBudget::read` failed.
+enum ReadError {
+    Io(std::io::Error),
+    TooLarge(AppError),
+}
+
+fn is_fit_entry(name: &str) -> bool {
+    let base = name.rsplit('/').next().unwrap_or(name);
+    // skips the resource forks macOS adds to archives
+    let base = base.to_lowercase();
+    !base.starts_with("._") && (base.ends_with(".fit") || base.ends_with(".fit.gz"))
+}
+
+/// Decompresses `file` when it is gzip-compressed, all members of it,
+/// stripping `.gz` from its name. The decompressed bytes are taken from
+/// `budget`, and the file fails when it decompresses to more than
+/// `MAX_FIT_FILE_BYTES` or more than is left of it.
+fn gunzip(file: UploadedFile, budget: &mut UnpackBudget) -> Result<UploadedFile, FileFailure> {
+    if !file.bytes.starts_with(GZIP_MAGIC) {
+        return Ok(file);
+    }
+    let bytes = match budget.read(&file.name, MultiGzDecoder::new(file.bytes.as_ref())) {
+        Ok(bytes) => bytes,
+        Err(ReadError::Io(e)) => {
+            let error = AppError::Parse {
+                message: format!("{} is not a valid gzip file", file.name),
+                details: e.to_string(),
+            };
+            return Err((file.name, error));
+        }
+        Err(ReadError::TooLarge(error)) => return Err((file.name, error)),
+    };
+    let name = match file.name.strip_suffix(".gz") {
+        Some(name) => name.to_owned(),
+        None => file.name,
+    };
+    Ok(UploadedFile {
+        name,
+        bytes: bytes.into(),
+    })
+}
+
+fn zip_error(name: &str, details: impl ToString) -> AppError {
+    AppError::Parse {
+        message: format!("{name} is not a valid ZIP archive"),
+        details: details.to_string(),
+    }
+}
+
+/// Hex SHA-256 of `bytes`.
+pub fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// A file that couldn't be read, by name.
+pub type FileFailure = (String, AppError);
+
+/// The FIT files in `file` when it is a ZIP archive, named
+/// `archive.zip/entry.fit`, or `file` itself otherwise, with gzip-compressed
+/// files and entries decompressed. Entries that can't be unpacked fail on
+/// their own. Unpacked bytes and entries are taken from `budget`, which is
+/// shared by all files of an upload.
+pub fn expand_archive(
+    file: UploadedFile,
+    budget: &mut UnpackBudget,
+) -> Vec<Result<UploadedFile, FileFailure>> {
+    if let Err(error) = budget.take_entries(&file.name, 1) {
+        return vec![Err((file.name, error))];
+    }
+    let file = match gunzip(file, budget) {
+        Ok(file) => file,
+        Err(failure) => return vec![Err(failure)],
+    };
+    if !file.bytes.starts_with(ZIP_MAGIC) {
+        return vec![Ok(file)];
+    }
+    let mut archive = match ZipArchive::new(Cursor::new(file.bytes)) {
+        Ok(archive) => archive,
+        Err(e) => return vec![Err((file.name.clone(), zip_error(&file.name, e)))],
+    };
+    if let Err(error) = budget.take_entries(&file.name, archive.len()) {
+        return vec![Err((file.name, error))];
+    }
+    (0..archive.len())
+        .filter_map(|index| {
+            let entry = match archive.by_index(index) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    let name = format!("{}/{index}", file.name);
+                    return Some(Err((name, zip_error(&file.name, e))));
+                }
+            };
+            if !entry.is_file() || !is_fit_entry(entry.name()) {
+                return None;
+            }
+            let name = format!("{}/{}", file.name, entry.name());
+            if entry.size() > MAX_FIT_FILE_BYTES {
+                let error =
+                    AppError::Validation(format!("{name} exceeds {MAX_FIT_FILE_BYTES} bytes"));
+                return Some(Err((name, error)));
+            }
+            // the declared size can't be trusted, so the cap applies while reading
+            Some(match budget.read(&name, entry) {
+                Ok(bytes) => gunzip(
+                    UploadedFile {
+                        name,
+                        bytes: bytes.into(),
+                    },
+                    budget,
+                ),
+                Err(ReadError::Io(e)) => Err((name, zip_error(&file.name, e))),
+                Err(ReadError::TooLarge(error)) => Err((name, error)),
+            })
+        })
+        .collect()
+}
+
+/// Parses and analyses `files` on the blocking pool, at most
+/// `MAX_CONCURRENT_ANALYSES` at a time. Results are in the order of `files`.
+pub async fn analyse_files(
+    files: Vec<UploadedFile>,
+    user_id: &str,
+    options: &AnalysisOptions,
+    profile: Option<&UserProfile>,
+) -> Vec<Result<MongoSchema, AppError>> {
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_ANALYSES));
+    let user_id: Arc<str> = Arc::from(user_id);
+    let options = Arc::new(options.clone());
+    let profile = Arc::new(profile.cloned());
+    let count = files.len();
+
+    let mut tasks = JoinSet::new();
+    for (index, file) in files.into_iter().enumerate() {
+        let permit = semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let (user_id, options, profile) = (user_id.clone(), options.clone(), profile.clone());
+        tasks.spawn_blocking(move || {
+            let _permit = permit;
+            let analyse = || -> Result<MongoSchema, AppError> {
+                let data = from_reader(&mut file.bytes.as_ref())?;
+                let mut mongo_doc =
+                    analyse_activity(&user_id, data, &options, profile.as_ref().as_ref());
+                mongo_doc.content_hash = Some(content_hash(&file.bytes));
+                Ok(mongo_doc)
+            };
+            // one malformed file must not take the rest of the upload down
+            let result = panic::catch_unwind(AssertUnwindSafe(analyse)).unwrap_or_else(|_| {
+                Err(AppError::Parse {
+                    message: format!("{} could not be analysed", file.name),
+                    details: "analysis panicked".to_owned(),
+                })
+            });
+            (index, result)
+        });
+    }
+
+    let mut results: Vec<Option<Result<MongoSchema, AppError>>> =
+        std::iter::repeat_with(|| None).take(count).collect();
+    while let Some(joined) = tasks.join_next().await {
+        let (index, result) = joined.expect("analysis panics are caught");
+        results[index] = Some(result);
+    }
+    results.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::*;
+
+    fn zip(entries: &[(&str, &[u8])]) -> UploadedFile {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, bytes) in entries {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(bytes).unwrap();
+        }
+        UploadedFile {
+            name: "rides.zip".to_owned(),
+            bytes: writer.finish().unwrap().into_inner().into(),
+        }
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn user_locks_are_removed_once_released() {
+        let locks = UserLocks::default();
+        let first = locks.lock("a").await;
+        let waiting = tokio::spawn({
+            let locks = locks.clone();
+            async move {
+                let _second = locks.lock("a").await;
+            }
+        });
+        let other = locks.lock("b").await;
+        assert_eq!(locks.0.lock().unwrap().len(), 2);
+        drop(other);
+        assert!(!locks.0.lock().unwrap().contains_key("b"));
+        let holders = || Arc::strong_count(&locks.0.lock().unwrap()["a"]);
+        while holders() < 3 {
+            tokio::task::yield_now().await;
+        }
+        // the waiting upload keeps the entry alive
+        drop(first);
+        assert!(locks.0.lock().unwrap().contains_key("a"));
+        waiting.await.unwrap();
+        assert!(locks.0.lock().unwrap().is_empty());
+    }
+
+    fn budget(bytes: u64, entries: usize) -> UnpackBudget {
+        UnpackBudget { bytes, entries }
+    }
+
+    #[test]
+    fn expands_fit_entries_of_archives() {
+        let archive = zip(&[
+            ("ride.fit", b"fit data"),
+            ("notes.txt", b"notes"),
+            ("__MACOSX/._ride.fit", b"fork"),
+        ]);
+        let files = expand_archive(archive, &mut UnpackBudget::default());
+        assert_eq!(files.len(), 1);
+        let file = files.into_iter().next().unwrap().unwrap();
+        assert_eq!(file.name, "rides.zip/ride.fit");
+        assert_eq!(file.bytes.as_ref(), b"fit data");
+    }
+
+    #[test]
+    fn unpacked_bytes_are_capped_per_upload() {
+        let archive = zip(&[("a.fit", &[0; 6]), ("b.fit", &[0; 6])]);
+        let mut budget = budget(10, MAX_UPLOAD_ENTRIES);
+        let files = expand_archive(archive, &mut budget);
+        assert!(files[0].is_ok());
+        assert!(matches!(files[1], Err((_, AppError::Validation(_)))));
+        assert_eq!(budget.bytes, 4);
+    }
+
+    #[test]
+    fn entries_are_capped_per_upload() {
+        let archive = zip(&[("a.fit", b"a"), ("b.fit", b"b"), ("c.fit", b"c")]);
+        let files = expand_archive(archive, &mut budget(MAX_UNPACKED_BYTES, 3));
+        assert_eq!(files.len(), 1);
+        assert!(matches!(files[0], Err((_, AppError::Validation(_)))));
+
+        let mut budget = budget(MAX_UNPACKED_BYTES, 1);
+        let file = || UploadedFile {
+            name: "ride.fit".to_owned(),
+            bytes: Bytes::from_static(b"fit data"),
+        };
+        assert!(expand_archive(file(), &mut budget)[0].is_ok());
+        assert!(expand_archive(file(), &mut budget)[0].is_err());
+    }
+
+    #[test]
+    fn decompresses_every_gzip_member() {
+        let mut bytes = gzip(b"fit ");
+        bytes.extend(gzip(b"data"));
+        let file = UploadedFile {
+            name: "ride.fit.gz".to_owned(),
+            bytes: bytes.into(),
+        };
+        let mut budget = UnpackBudget::default();
+        let file = expand_archive(file, &mut budget).pop().unwrap().unwrap();
+        assert_eq!(file.name, "ride.fit");
+        assert_eq!(file.bytes.as_ref(), b"fit data");
+        assert_eq!(budget.bytes, MAX_UNPACKED_BYTES - 8);
+    }
+
+    #[test]
+    fn gzip_output_counts_against_the_upload() {
+        let file = |bytes: Vec<u8>| UploadedFile {
+            name: "ride.fit.gz".to_owned(),
+            bytes: bytes.into(),
+        };
+        let mut shared = budget(10, MAX_UPLOAD_ENTRIES);
+        assert!(expand_archive(file(gzip(&[0; 6])), &mut shared)[0].is_ok());
+        let files = expand_archive(file(gzip(&[0; 6])), &mut shared);
+        assert!(matches!(files[0], Err((_, AppError::Validation(_)))));
+
+        // the compressed entry fits, what it decompresses to doesn't
+        let archive = zip(&[("ride.fit.gz", &gzip(&[0; 1000]))]);
+        let files = expand_archive(archive, &mut budget(500, MAX_UPLOAD_ENTRIES));
+        assert!(matches!(files[0], Err((_, AppError::Validation(_)))));
+    }
+}
@@ -0,0 +1,136 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ensure_positive, AppError};
+use crate::ftp::FtpMethod;
+use crate::zones::valid_zones;
+
+/// Athlete settings valid from `effective_from` until the next entry.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AthleteSettings {
+    pub effective_from: DateTime<Utc>,
+    pub ftp: Option<f64>,
+    /// Body weight in kilograms.
+    pub weight: Option<f64>,
+    pub max_hr: Option<f64>,
+    pub resting_hr: Option<f64>,
+    pub threshold_hr: Option<f64>,
+    /// Upper bounds in watts, replacing the FTP based Coggan zones.
+    pub power_zones: Option<Vec<f64>>,
+    /// Upper bounds in bpm, replacing the threshold/max HR based zones.
+    pub hr_zones: Option<Vec<f64>>,
+}
+
+impl AthleteSettings {
+    /// Rejects values the zone, IF and W/kg calculations can't work with.
+    pub fn validate(&self) -> Result<(), AppError> {
+        ensure_positive("ftp", self.ftp)?;
+        ensure_positive("weight", self.weight)?;
+        ensure_positive("max_hr", self.max_hr)?;
+        ensure_positive("resting_hr", self.resting_hr)?;
+        ensure_positive("threshold_hr", self.threshold_hr)?;
+        if let (Some(resting_hr), Some(max_hr)) = (self.resting_hr, self.max_hr) {
+            if resting_hr >= max_hr {
+                return Err(AppError::Validation(
+                    "resting_hr must be below max_hr".to_owned(),
+                ));
+            }
+        }
+        for (name, zones) in [
+            ("power_zones", &self.power_zones),
+            ("hr_zones", &self.hr_zones),
+        ] {
+            if zones.as_ref().is_some_and(|zones| !valid_zones(zones)) {
+                return Err(AppError::Validation(format!(
+                    "{name} must be ascending, positive numbers"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub user_id: String,
+    /// History of settings, ordered by `effective_from`.
+    #[serde(default)]
+    pub settings: Vec<AthleteSettings>,
+    /// Raise the FTP after an upload whenever this method estimates a higher one.
+    #[serde(default)]
+    pub auto_update_ftp: Option<FtpMethod>,
+}
+
+impl UserProfile {
+    /// The settings in effect at `at`, i.e. the latest entry not after it.
+    pub fn settings_at(&self, at: DateTime<Utc>) -> Option<&AthleteSettings> {
+        self.settings
+            .iter()
+            .rev()
+            .find(|settings| settings.effective_from <= at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn settings(ftp: f64) -> AthleteSettings {
+        AthleteSettings {
+            effective_from: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+            ftp: Some(ftp),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validates_settings() {
+        assert!(settings(250.0).validate().is_ok());
+        assert!(settings(0.0).validate().is_err());
+        assert!(settings(-10.0).validate().is_err());
+        let hr = AthleteSettings {
+            resting_hr: Some(190.0),
+            max_hr: Some(60.0),
+            ..settings(250.0)
+        };
+        assert!(hr.validate().is_err());
+        let zones = AthleteSettings {
+            power_zones: Some(vec![200.0, 150.0]),
+            ..settings(250.0)
+        };
+        assert!(zones.validate().is_err());
+        let weight = AthleteSettings {
+            weight: Some(0.0),
+            ..settings(250.0)
+        };
+        assert!(weight.validate().is_err());
+    }
+
+    #[test]
+    fn settings_apply_from_their_effective_date() {
+        let later = AthleteSettings {
+            effective_from: Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap(),
+            ..settings(280.0)
+        };
+        let profile = UserProfile {
+            user_id: "u".to_owned(),
+            settings: vec![settings(250.0), later.clone()],
+            auto_update_ftp: None,
+        };
+        let in_march = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        assert_eq!(
+            profile.settings_at(in_march).and_then(|s| s.ftp),
+            Some(250.0)
+        );
+        assert_eq!(
+            profile
+                .settings_at(later.effective_from)
+                .and_then(|s| s.ftp),
+            Some(280.0)
+        );
+        assert!(profile
+            .settings_at(Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap())
+            .is_none());
+    }
+}
@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+
+/// Seconds between two points of the stored W′bal series.
+const SERIES_STEP: usize = 5;
+
+/// Skiba W′ balance of an activity.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WPrimeBalance {
+    pub cp: f64,
+    pub w_prime: f64,
+    /// Recovery time constant in seconds.
+    pub tau: f64,
+    /// Lowest balance reached, in joules.
+    pub min_balance: f64,
+    /// Seconds spent below 25% of W′.
+    pub time_below_25_percent: usize,
+    /// `(seconds from start, balance in joules)` every `SERIES_STEP` seconds.
+    pub series: Vec<(usize, f32)>,
+}
+
+/// Skiba's integral W′bal model over `(unix timestamp, watts)` record
+/// samples. The recovery time constant depends on how far below CP the
+/// athlete recorded on average, which lets the integral be evaluated as a
+/// running exponential sum. Seconds without a sample, such as pauses, recover
+/// W′ as if riding at 0 W; duplicate and out-of-order samples are dropped.
+pub fn calculate_w_prime_balance(
+    samples: &[(i64, f64)],
+    cp: f64,
+    w_prime: f64,
+) -> Option<WPrimeBalance> {
+    let &(start, _) = samples.first()?;
+    if cp <= 0.0 || w_prime <= 0.0 {
+        return None;
+    }
+    let below_cp: Vec<f64> = samples
+        .iter()
+        .map(|&(_, p)| p)
+        .filter(|&p| p < cp)
+        .collect();
+    let dcp = if below_cp.is_empty() {
+        0.0
+    } else {
+        cp - below_cp.iter().sum::<f64>() / below_cp.len() as f64
+    };
+    let tau = 546.0 * (-0.01 * dcp).exp() + 316.0;
+    let decay = (-1.0 / tau).exp();
+
+    let mut expended: f64 = 0.0;
+    let mut min_balance = w_prime;
+    let mut time_below_25_percent = 0;
+    let mut series = Vec::new();
+    let mut last = start - 1;
+    for &(timestamp, p) in samples {
+        if timestamp <= last {
+            continue;
+        }
+        let missing = (timestamp - last - 1) as f64;
+        if missing > 0.0 && expended > 0.0 {
+            // seconds until the recovering balance climbs back above 25%
+            let below = ((0.75 * w_prime / expended).ln() / decay.ln()).clamp(0.0, missing);
+            time_below_25_percent += below.ceil() as usize;
+            expended *= decay.powf(missing);
+        }
+        expended = expended * decay + (p - cp).max(0.0);
+        let balance = w_prime - expended;
+        min_balance = min_balance.min(balance);
+        if balance < 0.25 * w_prime {
+            time_below_25_percent += 1;
+        }
+        let offset = (timestamp - start) as usize;
+        if series
+            .last()
+            .is_none_or(|&(previous, _)| offset / SERIES_STEP > previous / SERIES_STEP)
+        {
+            series.push((offset, balance as f32));
+        }
+        last = timestamp;
+    }
+
+    Some(WPrimeBalance {
+        cp,
+        w_prime,
+        tau,
+        min_balance,
+        time_below_25_percent,
+        series,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::steady;
+
+    #[test]
+    fn below_cp_keeps_w_prime_full() {
+        let balance = calculate_w_prime_balance(&steady(0, 600, 200.0), 250.0, 20_000.0).unwrap();
+        assert_eq!(balance.min_balance, 20_000.0);
+        assert_eq!(balance.time_below_25_percent, 0);
+        assert_eq!(balance.series.len(), 120);
+        assert_eq!(balance.series[1].0, SERIES_STEP);
+    }
+
+    #[test]
+    fn efforts_above_cp_deplete_w_prime() {
+        // 60 s at 100 W over CP spend at most 6 kJ
+        let balance = calculate_w_prime_balance(&steady(0, 60, 350.0), 250.0, 20_000.0).unwrap();
+        assert!(balance.min_balance < 20_000.0 && balance.min_balance >= 14_000.0);
+    }
+
+    #[test]
+    fn pauses_recover_w_prime_and_keep_offsets() {
+        let mut samples = steady(0, 120, 450.0);
+        samples.extend(steady(1_320, 10, 0.0));
+        let balance = calculate_w_prime_balance(&samples, 250.0, 30_000.0).unwrap();
+        let (_, after_effort) = balance.series.iter().find(|(t, _)| *t == 115).unwrap();
+        let (offset, after_pause) = *balance.series.last().unwrap();
+        assert!(offset >= 1_320);
+        assert!(after_pause > after_effort + 10_000.0);
+    }
+
+    #[test]
+    fn time_below_25_percent_counts_pauses() {
+        let mut samples = steady(0, 100, 500.0);
+        samples.extend(steady(400, 1, 0.0));
+        let balance = calculate_w_prime_balance(&samples, 250.0, 20_000.0).unwrap();
+        assert!(balance.time_below_25_percent > 100);
+    }
+
+    #[test]
+    fn needs_samples_and_a_valid_model() {
+        assert!(calculate_w_prime_balance(&[], 250.0, 20_000.0).is_none());
+        assert!(calculate_w_prime_balance(&steady(0, 10, 300.0), 0.0, 20_000.0).is_none());
+        assert!(calculate_w_prime_balance(&steady(0, 10, 300.0), 250.0, 0.0).is_none());
+    }
+}
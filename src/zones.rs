@@ -0,0 +1,142 @@
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+
+use crate::structures::TimeInZone;
+
+/// Upper bounds of Coggan's power zones 1–6 as a fraction of FTP; zone 7 is open.
+pub const COGGAN_POWER_ZONES: [f64; 6] = [0.55, 0.75, 0.90, 1.05, 1.20, 1.50];
+/// Upper bounds of Coggan's heart-rate zones 1–4 as a fraction of LTHR.
+pub const LTHR_ZONES: [f64; 4] = [0.68, 0.83, 0.94, 1.05];
+/// Upper bounds of the heart-rate zones 1–4 as a fraction of max HR.
+pub const MAX_HR_ZONES: [f64; 4] = [0.6, 0.7, 0.8, 0.9];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ZoneTime {
+    pub zone: usize,
+    pub low: f64,
+    /// `None` for the open-ended top zone.
+    pub high: Option<f64>,
+    pub seconds: usize,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TimeInZones {
+    pub power: Vec<ZoneTime>,
+    pub heart_rate: Vec<ZoneTime>,
+    /// Zones as reported by the device for the whole session, for comparison.
+    pub device: Option<TimeInZone>,
+}
+
+/// Scales relative zone bounds, e.g. `COGGAN_POWER_ZONES`, by a threshold.
+pub fn scale_zones(fractions: &[f64], threshold: f64) -> Vec<f64> {
+    fractions.iter().map(|f| f * threshold).collect()
+}
+
+/// Parses comma separated zone upper bounds such as `"150,200,250"`.
+pub fn parse_zones(bounds: &str) -> Option<Vec<f64>> {
+    let bounds: Vec<f64> = bounds
+        .split(',')
+        .map(|b| b.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    valid_zones(&bounds).then_some(bounds)
+}
+
+/// Whether `bounds` are usable zone upper bounds: at least one, all positive
+/// and strictly ascending.
+pub fn valid_zones(bounds: &[f64]) -> bool {
+    !bounds.is_empty()
+        && bounds.iter().all(|b| b.is_finite() && *b > 0.0)
+        && bounds.windows(2).all(|w| w[0] < w[1])
+}
+
+/// Deserializes an optional comma separated list of zone bounds.
+pub fn deserialize_zones<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<f64>>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|bounds| {
+            parse_zones(&bounds)
+                .ok_or_else(|| D::Error::custom("zones must be ascending, comma separated numbers"))
+        })
+        .transpose()
+}
+
+/// Seconds spent in each zone of a 1 Hz series. `upper_bounds` are exclusive
+/// and ascending; everything at or above the last bound is the top zone.
+pub fn time_in_zones(segments: &[Vec<f64>], upper_bounds: &[f64]) -> Vec<ZoneTime> {
+    if upper_bounds.is_empty() {
+        return vec![];
+    }
+    let mut seconds = vec![0; upper_bounds.len() + 1];
+    for &value in segments.iter().flatten() {
+        let zone = upper_bounds.partition_point(|&bound| bound <= value);
+        seconds[zone] += 1;
+    }
+    seconds
+        .into_iter()
+        .enumerate()
+        .map(|(i, seconds)| ZoneTime {
+            zone: i + 1,
+            low: if i == 0 { 0.0 } else { upper_bounds[i - 1] },
+            high: upper_bounds.get(i).copied(),
+            seconds,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(zones: &[ZoneTime]) -> Vec<usize> {
+        zones.iter().map(|zone| zone.seconds).collect()
+    }
+
+    #[test]
+    fn values_on_a_bound_count_in_the_higher_zone() {
+        let zones = time_in_zones(&[vec![99.0, 100.0, 199.0, 200.0]], &[100.0, 200.0]);
+        assert_eq!(seconds(&zones), vec![1, 2, 1]);
+        assert_eq!((zones[1].low, zones[1].high), (100.0, Some(200.0)));
+    }
+
+    #[test]
+    fn top_zone_is_open() {
+        let zones = time_in_zones(&[vec![150.0], vec![5000.0, 250.0]], &[100.0, 200.0]);
+        assert_eq!(seconds(&zones), vec![0, 1, 2]);
+        let top = zones.last().unwrap();
+        assert_eq!((top.zone, top.low, top.high), (3, 200.0, None));
+        assert_eq!(zones[0].low, 0.0);
+    }
+
+    #[test]
+    fn no_bounds_no_zones() {
+        assert!(time_in_zones(&[vec![150.0]], &[]).is_empty());
+    }
+
+    #[test]
+    fn scales_relative_bounds() {
+        assert_eq!(scale_zones(&[0.5, 1.0], 250.0), vec![125.0, 250.0]);
+        assert_eq!(
+            scale_zones(&COGGAN_POWER_ZONES, 200.0)[3],
+            COGGAN_POWER_ZONES[3] * 200.0
+        );
+    }
+
+    #[test]
+    fn parses_zone_strings() {
+        assert_eq!(parse_zones("150, 200,250"), Some(vec![150.0, 200.0, 250.0]));
+        assert_eq!(parse_zones("150"), Some(vec![150.0]));
+        for rejected in [
+            "", "150,fast", "200,150", "150,150", "0,150", "-5,150", "150,inf",
+        ] {
+            assert_eq!(parse_zones(rejected), None, "{rejected:?}");
+        }
+    }
+
+    #[test]
+    fn valid_zones_need_ascending_positive_bounds() {
+        assert!(valid_zones(&[100.0, 200.0]));
+        assert!(!valid_zones(&[]));
+        assert!(!valid_zones(&[200.0, 100.0]));
+        assert!(!valid_zones(&[f64::NAN]));
+    }
+}